@@ -0,0 +1,50 @@
+#[macro_use]
+extern crate criterion;
+extern crate rand;
+extern crate xx_bloomfilter;
+
+use criterion::{black_box, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use xx_bloomfilter::Bloom;
+
+const SIZES: [usize; 3] = [1_000, 100_000, 10_000_000];
+
+fn keys(n: usize) -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..n).map(|_| rng.gen()).collect()
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let keys = keys(1_000);
+    let mut group = c.benchmark_group("insert");
+    for items_count in SIZES.iter() {
+        let mut bloom = Bloom::new_with_rate(*items_count, 1e-3);
+        group.bench_with_input(BenchmarkId::from_parameter(items_count), &keys, |b, keys| {
+            b.iter(|| {
+                for key in keys {
+                    bloom.add(black_box(key));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_check(c: &mut Criterion) {
+    let keys = keys(1_000);
+    let mut group = c.benchmark_group("check");
+    for items_count in SIZES.iter() {
+        let mut bloom = Bloom::new_with_rate(*items_count, 1e-3);
+        for key in keys.iter().step_by(2) {
+            bloom.add(key);
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(items_count), &keys, |b, keys| {
+            b.iter(|| keys.iter().filter(|key| bloom.check(black_box(*key))).count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_check);
+criterion_main!(benches);
@@ -3,19 +3,98 @@
 //! This is a simple but fast Bloom filter implementation, that requires only
 //! 2 hash functions, generated with XXHash64 using randomized keys.
 
+#[cfg(feature = "serde")]
+extern crate base64;
 extern crate bit_vec;
 extern crate rand;
 extern crate twox_hash;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[cfg(feature = "serde")]
+use base64::Engine;
 use bit_vec::BitVec;
 use std::cmp;
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::error;
 use std::f64;
+use std::fmt;
+use std::fs::File;
 use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "bench")]
+use std::time::Instant;
 use twox_hash::XxHash64;
 
+/// Largest bitmap, in bits, that `to_ascii` will render bit by bit
+pub const ASCII_MAX_BITS: u64 = 4096;
+
+/// Number of seed draws `restrict_to` makes before giving up on rejecting every candidate
+pub const RESTRICT_MAX_ATTEMPTS: usize = 32;
+
+/// Lowest false positive rate `new_bounded_probes` sizes a filter for, so a generous byte
+/// budget isn't spent on a gain no caller could measure
+pub const BOUNDED_PROBES_MIN_FP: f64 = 1e-9;
+
+/// Largest bitmap, in bits, accepted from untrusted input by `from_delta_encoded`, `from_rle`
+/// and the conversion from JSON, 2^32 bits or 512 MiB, so that a forged size can't exhaust memory
+pub const MAX_UNTRUSTED_BITS: u64 = 1 << 32;
+
+/// Largest number of hash functions accepted by `from_bytes`, far above the optimal k of any
+/// practical false positive rate, so that a forged header can't make every lookup crawl
+pub const MAX_UNTRUSTED_K: u64 = 256;
+
+/// Magic bytes starting a filter serialized with `to_bytes`
+pub const MAGIC: [u8; 4] = *b"XXBF";
+
+/// Version of the format written by `to_bytes`
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Identifier of the scheme deriving bit offsets from an item's hashes,
+/// stored by `to_bytes` so filters built with another scheme are not misread
+pub const HASH_SCHEME: u8 = 1;
+
+/// Scheme written by `to_bytes` for filters built with `new_with_hashers`, whose hash
+/// functions aren't serialized, so that `from_bytes` rejects them
+pub const CUSTOM_HASH_SCHEME: u8 = 255;
+
+const VERSION_OFFSET: usize = 4;
+const SCHEME_OFFSET: usize = 5;
+const BITMAP_SIZE_OFFSET: usize = 6;
+const K_OFFSET: usize = 14;
+const SEEDS_OFFSET: usize = 22;
+const BITMAP_OFFSET: usize = 38;
+
+/// Identifier reported by `from_upstream` for the SipHash-1-3 scheme of the upstream
+/// bloomfilter crate
+pub const UPSTREAM_HASH_SCHEME: u8 = 0;
+
+// Cost model of `estimated_query_cycles`: cycles to hash an item with both hash functions,
+// cycles per probe on a cached line, and cache assumed available to the filter
+const HASH_CYCLES: f64 = 20.0;
+const PROBE_CYCLES: f64 = 2.0;
+const CACHE_BYTES: f64 = (1 << 20) as f64;
+
+const UPSTREAM_VERSION: u8 = 1;
+const UPSTREAM_HEADER_LEN: usize = 1 + 8 + 4 + 32;
+
+type ByteHasher = Arc<dyn Fn(&[u8]) -> u64 + Send + Sync>;
+
+/// bitmap_size, k, the seeds and the MSB-first bitmap bytes, as returned by `to_columns`
+pub type Columns = (u64, u64, (u64, u64), Vec<u8>);
+
 /// Bloom filter structure
 pub struct Bloom {
     bitmap: BitVec,
@@ -23,6 +102,24 @@ pub struct Bloom {
     k: u64,
     seeds: (u64, u64),
     xx: (XxHash64, XxHash64),
+    insertions: u64,
+    ones: u64,
+    root: u64,
+    recent: Option<(usize, Vec<(u64, u64)>)>,
+    hashers: Option<(ByteHasher, ByteHasher)>,
+}
+
+/// Hasher collecting the bytes an item feeds to it, for caller-provided hash functions
+struct ByteCollector(Vec<u8>);
+
+impl Hasher for ByteCollector {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        0
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,7 +127,143 @@ pub struct SerdeBloom {
     bitmap: Vec<u8>,
     bitmap_size: u64,
     k: u64,
-    seeds: (u64, u64)
+    seeds: (u64, u64),
+    #[serde(default)]
+    insertions: u64,
+    #[serde(default)]
+    root: u64,
+}
+
+/// Errors returned when an operation can't be carried out on the given filters
+#[derive(Debug, Clone, PartialEq)]
+pub enum BloomError {
+    /// The filters don't share the same bitmap size, number of hash functions and seeds
+    Incompatible,
+    /// A bitmap doesn't have the number of bits of the filter
+    SizeMismatch,
+    /// The bytes are not a filter serialized with `to_bytes`, or are truncated
+    InvalidFormat,
+    /// The serialized format version is not supported
+    UnsupportedVersion(u8),
+    /// The filter was serialized with another hashing scheme than `HASH_SCHEME`
+    SchemeMismatch(u8),
+    /// A buffer is too small to hold the filter
+    BufferTooSmall,
+    /// A field of a JSON configuration is missing or invalid
+    InvalidField(&'static str),
+    /// No filter meets the requested constraints
+    Infeasible,
+    /// The filter hashes with functions given to `new_with_hashers`, which can't be serialized
+    CustomHashers,
+    /// More set bits were requested than the bitmap has
+    TooManyOnes,
+}
+
+impl fmt::Display for BloomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BloomError::Incompatible => write!(f, "bloom filters have different parameters"),
+            BloomError::SizeMismatch => write!(f, "bitmap size doesn't match the filter"),
+            BloomError::InvalidFormat => write!(f, "not a serialized bloom filter"),
+            BloomError::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            BloomError::SchemeMismatch(scheme) => {
+                write!(f, "hashing scheme {} doesn't match scheme {}", scheme, HASH_SCHEME)
+            }
+            BloomError::BufferTooSmall => write!(f, "buffer too small for the filter"),
+            BloomError::InvalidField(field) => write!(f, "missing or invalid field `{}`", field),
+            BloomError::Infeasible => write!(f, "no filter meets the requested constraints"),
+            BloomError::CustomHashers => write!(f, "custom hash functions can't be serialized"),
+            BloomError::TooManyOnes => write!(f, "more set bits requested than the bitmap has"),
+        }
+    }
+}
+
+impl error::Error for BloomError {}
+
+/// Parameters that differ between two filters, as `(self, other)` pairs
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamDiff {
+    pub bitmap_size: Option<(u64, u64)>,
+    pub bitmap_len: Option<(usize, usize)>,
+    pub k: Option<(u64, u64)>,
+    pub seeds: Option<((u64, u64), (u64, u64))>,
+}
+
+impl ParamDiff {
+    /// Return true if no parameter differs, meaning the filters are compatible unless one
+    /// of them was built with `new_with_hashers`
+    pub fn is_empty(&self) -> bool {
+        self.bitmap_size.is_none() && self.bitmap_len.is_none() && self.k.is_none() && self.seeds.is_none()
+    }
+}
+
+impl fmt::Display for ParamDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no differences");
+        }
+        let mut diffs = Vec::new();
+        if let Some((ours, theirs)) = self.bitmap_size {
+            diffs.push(format!("bitmap_size: {} != {}", ours, theirs));
+        }
+        if let Some((ours, theirs)) = self.bitmap_len {
+            diffs.push(format!("bitmap_len: {} != {}", ours, theirs));
+        }
+        if let Some((ours, theirs)) = self.k {
+            diffs.push(format!("k: {} != {}", ours, theirs));
+        }
+        if let Some((ours, theirs)) = self.seeds {
+            diffs.push(format!("seeds: {:?} != {:?}", ours, theirs));
+        }
+        write!(f, "{}", diffs.join(", "))
+    }
+}
+
+/// An operation on a filter, for replaying recorded workloads through `apply`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BloomOp<T> {
+    Add(T),
+    Check(T),
+    CheckAndAdd(T),
+}
+
+/// The result of a `BloomOp`, holding the returned state for checks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BloomResult {
+    Added,
+    Checked(bool),
+    CheckedAndAdded(bool),
+}
+
+/// Byte ranges of the fields of a filter serialized with `to_bytes`.
+/// Integers are little-endian, the bitmap runs from `bitmap_offset` to the end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomLayout {
+    pub magic: Range<usize>,
+    pub version: Range<usize>,
+    pub scheme: Range<usize>,
+    pub bitmap_size: Range<usize>,
+    pub k: Range<usize>,
+    pub seeds: Range<usize>,
+    pub bitmap_offset: usize,
+}
+
+/// The outcome of `add_throttled`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddOutcome {
+    Inserted,
+    ThrottledWouldExceedFp,
+    AlreadyPresent,
+}
+
+/// Coarse state of a filter, see `Bloom::health`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    /// No bit is set
+    Empty,
+    Normal,
+    /// The false positive rate is at least 0.99, so nearly every lookup succeeds
+    Saturated,
 }
 
 impl Default for Bloom {
@@ -39,25 +272,80 @@ impl Default for Bloom {
     }
 }
 
-impl From<&Bloom> for SerdeBloom {
-    fn from(bloom: &Bloom) -> Self {
-        Self {
+/// Filters are equal when they have the same parameters and bitmap, whatever their history
+impl PartialEq for Bloom {
+    fn eq(&self, other: &Bloom) -> bool {
+        self.is_compatible(other) && self.bitmap == other.bitmap
+    }
+}
+
+/// Fails with `BloomError::CustomHashers` for filters built with `new_with_hashers`
+impl TryFrom<&Bloom> for SerdeBloom {
+    type Error = BloomError;
+
+    fn try_from(bloom: &Bloom) -> Result<Self, Self::Error> {
+        bloom.check_serializable()?;
+        Ok(Self {
             bitmap: bloom.bitmap.to_bytes(),
             bitmap_size: bloom.bitmap_size,
             k: bloom.k,
-            seeds: bloom.seeds
-        }
+            seeds: bloom.seeds,
+            insertions: bloom.insertions,
+            root: bloom.root,
+        })
     }
 }
 
 impl From<&SerdeBloom> for Bloom {
     fn from(serde_bloom: &SerdeBloom) -> Self {
-        Bloom::from_existing(
+        let mut bloom = Bloom::from_existing(
             serde_bloom.bitmap.as_slice(),
             serde_bloom.bitmap_size,
             serde_bloom.k,
             serde_bloom.seeds
-        )
+        );
+        bloom.insertions = serde_bloom.insertions;
+        bloom.root = serde_bloom.root;
+        bloom
+    }
+}
+
+/// Build a filter from a JSON object with `bitmap_size` in bits, at most `MAX_UNTRUSTED_BITS`,
+/// `k`, `seeds` as an array of two integers and an optional `bitmap` of base64 bytes, the
+/// filter being empty without it
+#[cfg(feature = "serde")]
+impl TryFrom<serde_json::Value> for Bloom {
+    type Error = BloomError;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        let positive = |name: &'static str| {
+            value
+                .get(name)
+                .and_then(|field| field.as_u64())
+                .filter(|&field| field > 0)
+                .ok_or(BloomError::InvalidField(name))
+        };
+        let bitmap_size = positive("bitmap_size")?;
+        let len = Bloom::untrusted_bitmap_len(bitmap_size).ok_or(BloomError::InvalidField("bitmap_size"))?;
+        let k = positive("k")?;
+        let seeds = match value.get("seeds").and_then(|seeds| seeds.as_array()).map(|seeds| seeds.as_slice()) {
+            Some([seed0, seed1]) => seed0.as_u64().zip(seed1.as_u64()),
+            _ => None,
+        };
+        let seeds = seeds.ok_or(BloomError::InvalidField("seeds"))?;
+        match value.get("bitmap") {
+            None | Some(serde_json::Value::Null) => {
+                Ok(Bloom::from_bitvec(BitVec::from_elem(len, false), k, seeds))
+            }
+            Some(bitmap) => {
+                let bitmap = bitmap
+                    .as_str()
+                    .and_then(|bitmap| base64::engine::general_purpose::STANDARD.decode(bitmap).ok())
+                    .filter(|bitmap| bitmap.len() as u64 == bitmap_size.div_ceil(8))
+                    .ok_or(BloomError::InvalidField("bitmap"))?;
+                Ok(Bloom::from_existing(&bitmap, bitmap_size, k, seeds))
+            }
+        }
     }
 }
 
@@ -66,11 +354,21 @@ impl Bloom {
     /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
     /// items_count is an estimation of the maximum number of items to store.
     pub fn new(bitmap_size: usize, items_count: usize) -> Self {
+        Self::new_with_seeds(bitmap_size, items_count, (rand::random(), rand::random()))
+    }
+
+    /// Create a new bloom filter structure like `new`, drawing the seeds from session_rng
+    /// instead of the thread RNG, so that a seeded session builds the same filters every run.
+    pub fn new_reproducible<R: Rng>(bitmap_size: usize, items_count: usize, session_rng: &mut R) -> Self {
+        let seeds = (session_rng.gen(), session_rng.gen());
+        Self::new_with_seeds(bitmap_size, items_count, seeds)
+    }
+
+    fn new_with_seeds(bitmap_size: usize, items_count: usize, seeds: (u64, u64)) -> Self {
         assert!(bitmap_size > 0 && items_count > 0);
         let bitmap_size = (bitmap_size as u64) * 8u64;
         let k = Self::optimal_k_num(bitmap_size, items_count);
         let bitmap = BitVec::from_elem(bitmap_size as usize, false);
-        let seeds = (rand::random(), rand::random());
         let xx = (Self::xx_new(seeds.0), Self::xx_new(seeds.1));
         Self {
             bitmap,
@@ -78,6 +376,11 @@ impl Bloom {
             k,
             seeds,
             xx,
+            insertions: 0,
+            ones: 0,
+            root: 0,
+            recent: None,
+            hashers: None,
         }
     }
 
@@ -103,19 +406,350 @@ impl Bloom {
         Bloom::new(bitmap_size, items_count)
     }
 
+    /// Create a new bloom filter structure probing at most max_k bits per item.
+    /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
+    /// items_count is an estimation of the maximum number of items to store.
+    /// When the optimal number of hash functions is above max_k, lookups stay bounded
+    /// but the false positive rate rises to (1 - e^(-max_k * n / m))^max_k.
+    pub fn new_capped_k(bitmap_size: usize, items_count: usize, max_k: u64) -> Self {
+        assert!(max_k > 0);
+        let mut bloom = Bloom::new(bitmap_size, items_count);
+        bloom.k = cmp::min(bloom.k, max_k);
+        bloom
+    }
+
+    /// Create a new bloom filter structure for a read-heavy workload.
+    /// items_count is an estimation of the maximum number of items to store,
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[, and
+    /// negative_query_fraction is the expected fraction of lookups for absent items, in [0.0, 1.0].
+    /// Lookups for absent items stop at the first unset bit, so fewer hash functions make
+    /// them cheaper. The bitmap is sized for fp_p, then the smallest number of hash functions
+    /// keeping the false positive rate under fp_p * (1 + negative_query_fraction) is used,
+    /// falling back to the classic optimum if none does.
+    pub fn new_optimized_for_reads(items_count: usize, fp_p: f64, negative_query_fraction: f64) -> Self {
+        assert!((0.0..=1.0).contains(&negative_query_fraction));
+        let mut bloom = Bloom::new_with_rate(items_count, fp_p);
+        let budget = fp_p * (1.0 + negative_query_fraction);
+        if let Some(k) = (1..bloom.k)
+            .find(|&k| Self::false_positive_rate(bloom.bitmap_size, k, items_count as f64) <= budget)
+        {
+            bloom.k = k;
+        }
+        bloom
+    }
+
+    /// Create a bloom filter holding the keys of a file, with a fp_p rate of false positives.
+    /// The file is a plain array of little-endian u64 keys, sorted and without duplicates
+    /// so that its length gives the number of items. It is read in a single buffered pass.
+    pub fn build_from_sorted_file<P: AsRef<Path>>(path: P, fp_p: f64) -> io::Result<Bloom> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let items_count = usize::try_from(len / 8).ok().filter(|_| len != 0 && len % 8 == 0);
+        let items_count = items_count.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "file length is not a non-zero multiple of 8 bytes")
+        })?;
+        let mut reader = BufReader::new(file);
+        let mut bloom = Bloom::new_with_rate(items_count, fp_p);
+        for _ in 0..items_count {
+            bloom.add(&Self::read_u64(&mut reader)?);
+        }
+        Ok(bloom)
+    }
+
+    /// Create a new bloom filter structure for keys drawn from a skewed distribution.
+    /// Each histogram entry is a range of keys with the number of items expected to be stored
+    /// from it, and lookups are assumed to hit each range in proportion to that number.
+    /// Lookups in densely stored ranges are mostly for present items, which can't be false
+    /// positives, so the filter is sized for fp_p false positives per lookup overall rather
+    /// than per lookup of an absent item, with the rate for absent items capped at 0.5.
+    pub fn new_for_histogram(histogram: &[(Range<u64>, f64)], fp_p: f64) -> Self {
+        assert!(fp_p > 0.0 && fp_p < 1.0);
+        let items: f64 = histogram.iter().map(|&(_, items)| items).sum();
+        assert!(items > 0.0);
+        let negative_fraction: f64 = histogram
+            .iter()
+            .map(|(range, range_items)| {
+                let keys = (range.end - range.start) as f64;
+                range_items / items * (1.0 - (range_items / keys).min(1.0))
+            })
+            .sum();
+        let absent_fp_p = (fp_p / negative_fraction).min(0.5);
+        Bloom::new_with_rate(items.ceil() as usize, absent_fp_p)
+    }
+
+    /// Create a filter holding keys with a measured false positive rate within max_fp.
+    /// Up to rng_seeds_to_try random seed pairs are tried, each filter being sized for
+    /// max_fp and checked against 10_000 random keys, assumed absent, standing in for the
+    /// complement of keys. Returns `None` if no seed pair qualifies.
+    pub fn new_verified<T: Hash>(keys: &[T], max_fp: f64, rng_seeds_to_try: usize) -> Option<Bloom> {
+        const SAMPLE: usize = 10_000;
+        let items_count = cmp::max(keys.len(), 1);
+        let bitmap_size = Self::compute_bitmap_size(items_count, max_fp);
+        let mut rng = rand::thread_rng();
+        for _ in 0..rng_seeds_to_try {
+            let mut bloom = Self::new_with_seeds(bitmap_size, items_count, rng.gen());
+            for key in keys {
+                bloom.add(key);
+            }
+            let false_positives = (0..SAMPLE).filter(|_| bloom.check(&rng.gen::<u64>())).count();
+            if false_positives as f64 / SAMPLE as f64 <= max_fp {
+                return Some(bloom);
+            }
+        }
+        None
+    }
+
+    /// Create a new bloom filter structure hashing the bytes each item feeds to `Hash::hash`
+    /// with h1 and h2 instead of the seeded XXHash64 pair.
+    /// Both functions should spread their output uniformly over all 64 bits and be
+    /// independent of each other, since every probe is derived as h1 + i * h2. The closures
+    /// aren't serialized: `to_bytes` and `to_buffer` write `CUSTOM_HASH_SCHEME`, which
+    /// `from_bytes` rejects, and the other serializations fail with `BloomError::CustomHashers`.
+    /// Such filters are never compatible with another, so they can't be combined either.
+    pub fn new_with_hashers<H1, H2>(bitmap_size: usize, items_count: usize, h1: H1, h2: H2) -> Self
+    where
+        H1: Fn(&[u8]) -> u64 + Send + Sync + 'static,
+        H2: Fn(&[u8]) -> u64 + Send + Sync + 'static,
+    {
+        let mut bloom = Self::new_with_seeds(bitmap_size, items_count, (0, 0));
+        bloom.hashers = Some((Arc::new(h1), Arc::new(h2)));
+        bloom
+    }
+
+    /// Create a new bloom filter structure using at most `max_bytes` bytes and `max_k` hash
+    /// functions, along with its false positive rate at `items_count` items as reported by
+    /// `corrected_fp_rate`. No more bytes are used than needed for `BOUNDED_PROBES_MIN_FP`
+    /// with the capped k, starting from m = -k * n / ln(1 - fp^(1/k)) bits once k is below
+    /// optimal and growing by sixteenths while probe collisions keep the rate above it.
+    /// Return `BloomError::Infeasible` if the rate would exceed 0.5.
+    pub fn new_bounded_probes(
+        items_count: usize,
+        max_bytes: usize,
+        max_k: u64,
+    ) -> Result<(Self, f64), BloomError> {
+        assert!(items_count > 0 && max_k > 0);
+        if max_bytes == 0 {
+            return Err(BloomError::Infeasible);
+        }
+        let mut needed = Self::compute_bitmap_size(items_count, BOUNDED_PROBES_MIN_FP);
+        if Self::optimal_k_num(needed as u64 * 8, items_count) > max_k {
+            let k = max_k as f64;
+            let bits = -k * items_count as f64 / f64::ln_1p(-BOUNDED_PROBES_MIN_FP.powf(1.0 / k));
+            needed = (bits / 8.0).ceil().min(usize::MAX as f64) as usize;
+        }
+        let mut bytes = cmp::min(max_bytes, needed);
+        loop {
+            let bloom = Self::new_capped_k(bytes, items_count, max_k);
+            let fp_rate = bloom.corrected_fp_rate(items_count);
+            if fp_rate > 0.5 {
+                return Err(BloomError::Infeasible);
+            }
+            if fp_rate <= BOUNDED_PROBES_MIN_FP || bytes == max_bytes {
+                return Ok((bloom, fp_rate));
+            }
+            bytes = cmp::min(max_bytes, bytes + bytes.div_ceil(16));
+        }
+    }
+
+    /// Create a new bloom filter structure whose false positive rate varies little across items.
+    /// Double hashing makes some items probe fewer distinct bits than others, giving them a
+    /// higher false positive rate. Up to rng_seeds_to_try random seed pairs are tried and the
+    /// one with the lowest variance of `effective_k` over a sample of keys is kept.
+    pub fn new_balanced(items_count: usize, fp_p: f64, rng_seeds_to_try: usize) -> Self {
+        assert!(rng_seeds_to_try > 0);
+        let bitmap_size = Self::compute_bitmap_size(items_count, fp_p);
+        let mut rng = rand::thread_rng();
+        (0..rng_seeds_to_try)
+            .map(|_| Self::new_with_seeds(bitmap_size, items_count, rng.gen()))
+            .map(|bloom| (bloom.effective_k_variance(1_000, 0), bloom))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal))
+            .map(|(_, bloom)| bloom)
+            .unwrap()
+    }
+
+    /// Create a new bloom filter structure guarding a more expensive downstream lookup.
+    /// items_count is an estimation of the maximum number of items to store, and
+    /// downstream_cost_ratio is the cost of a false positive, which triggers a useless
+    /// downstream lookup, relative to the cost of storing one bit per item.
+    /// With b bits per item and the optimal number of hash functions, the cost per item is
+    /// b + downstream_cost_ratio * e^(-b * ln(2)^2), which is lowest for a false positive
+    /// rate of 1 / (downstream_cost_ratio * ln(2)^2). That rate is capped at 0.5 for cheap
+    /// downstream lookups, where the filter barely pays off.
+    pub fn new_guard(items_count: usize, downstream_cost_ratio: f64) -> Self {
+        assert!(downstream_cost_ratio > 0.0);
+        let log2 = f64::consts::LN_2;
+        let fp_p = (1.0 / (downstream_cost_ratio * log2 * log2)).min(0.5);
+        Bloom::new_with_rate(items_count, fp_p)
+    }
+
+    /// Create a new bloom filter structure whose seeds are derived from its name,
+    /// so that filters created with the same name on any node are compatible.
+    /// items_count is an estimation of the maximum number of items to store,
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    pub fn new_named(name: &str, items_count: usize, fp_p: f64) -> Self {
+        let seed = |key| {
+            let mut hasher = Self::xx_new(key);
+            hasher.write(name.as_bytes());
+            hasher.finish()
+        };
+        Self::new_for_union(items_count, fp_p, (seed(0), seed(1)))
+    }
+
+    /// Create an empty bloom filter meant to be merged with others using `union`.
+    /// items_count is the number of items expected after all the filters are merged,
+    /// fp_p is the wanted rate of false positives of the merged filter.
+    /// Filters created with the same arguments share their parameters and can be unioned.
+    pub fn new_for_union(items_count: usize, fp_p: f64, seeds: (u64, u64)) -> Self {
+        let bitmap_size = Self::compute_bitmap_size(items_count, fp_p);
+        Self::new_with_seeds(bitmap_size, items_count, seeds)
+    }
+
+    /// Create a new bloom filter structure from pairs of base hashes computed elsewhere,
+    /// as returned by `hash_pair` for a filter with the same seeds.
+    /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
+    /// items_count is an estimation of the maximum number of items to store.
+    pub fn from_hash_pairs<I>(bitmap_size: usize, items_count: usize, seeds: (u64, u64), pairs: I) -> Self
+    where
+        I: Iterator<Item = (u64, u64)>,
+    {
+        let mut bloom = Self::new_with_seeds(bitmap_size, items_count, seeds);
+        for hashes in pairs {
+            bloom.add_hashes(hashes);
+        }
+        bloom
+    }
+
+    /// Create a bloom filter structure whose MSB-first bitmap of bitmap_size bits is stored
+    /// in buffer at offset, which lets many filters share a single allocation.
+    /// The bytes already in the buffer are used as the bitmap, so a zeroed buffer
+    /// gives an empty filter and a filter can be reopened later with the same arguments.
+    pub fn new_in_buffer(
+        buffer: &mut [u8],
+        offset: usize,
+        bitmap_size: u64,
+        k: u64,
+        seeds: (u64, u64),
+    ) -> Result<BufferBloom<'_>, BloomError> {
+        assert!(bitmap_size > 0);
+        let end = offset
+            .checked_add(bitmap_size.div_ceil(8) as usize)
+            .filter(|&end| end <= buffer.len())
+            .ok_or(BloomError::BufferTooSmall)?;
+        Ok(BufferBloom {
+            bitmap: &mut buffer[offset..end],
+            bitmap_size,
+            k,
+            xx: (Self::xx_new(seeds.0), Self::xx_new(seeds.1)),
+        })
+    }
+
+    /// Create a bloom filter structure holding the candidates accepted by oracle,
+    /// sized for their number with a fp_p rate of false positives.
+    pub fn from_membership_oracle<T: Hash, F: Fn(&T) -> bool>(candidates: &[T], oracle: F, fp_p: f64) -> Bloom {
+        let members: Vec<&T> = candidates.iter().filter(|item| oracle(item)).collect();
+        let mut bloom = Bloom::new_with_rate(cmp::max(members.len(), 1), fp_p);
+        for item in members {
+            bloom.add(item);
+        }
+        bloom
+    }
+
+    /// Create a filter with deliberately non-uniform fill, for testing: the bitmap is made of
+    /// one region of region_bits bits per entry of densities, with that fraction of the
+    /// region's bits set at random. The filter holds no actual items.
+    pub fn from_density_map(densities: &[f64], region_bits: usize, seeds: (u64, u64), k: u64) -> Self {
+        assert!(!densities.is_empty() && region_bits > 0);
+        let bitmap_size = densities.len() * region_bits;
+        let mut bitmap = BitVec::from_elem(bitmap_size, false);
+        let mut rng = rand::thread_rng();
+        for (region, &density) in densities.iter().enumerate() {
+            assert!((0.0..=1.0).contains(&density));
+            let ones = (density * region_bits as f64).round() as usize;
+            for offset in rand::seq::index::sample(&mut rng, region_bits, ones).iter() {
+                bitmap.set(region * region_bits + offset, true);
+            }
+        }
+        Self::from_bitvec(bitmap, k, seeds)
+    }
+
+    /// Create a filter with bitmap_size bits of which ones_count, picked at random from
+    /// rng_seed, are set, matching the statistics of a filter whose bitmap wasn't kept.
+    /// This does NOT restore membership: the filter holds none of the original items
+    /// and reports random ones as present. This is `with_exact_ones`, panicking where it
+    /// would return an error.
+    pub fn approximate_from_stats(bitmap_size: u64, k: u64, seeds: (u64, u64), ones_count: u64, rng_seed: u64) -> Self {
+        Self::with_exact_ones(bitmap_size, k, seeds, ones_count, rng_seed).unwrap()
+    }
+
+    fn from_bitvec(bitmap: BitVec, k: u64, seeds: (u64, u64)) -> Self {
+        let ones = Self::popcount(&bitmap);
+        Self {
+            bitmap_size: bitmap.len() as u64,
+            bitmap,
+            k,
+            seeds,
+            xx: (Self::xx_new(seeds.0), Self::xx_new(seeds.1)),
+            insertions: 0,
+            ones,
+            root: 0,
+            recent: None,
+            hashers: None,
+        }
+    }
+
+    /// Create a filter of bitmap_size bits, for testing, where each bit is set independently
+    /// with probability fill_prob from rng_seed. The same arguments give the same bitmap.
+    pub fn random(bitmap_size: u64, k: u64, seeds: (u64, u64), fill_prob: f64, rng_seed: u64) -> Self {
+        assert!(bitmap_size > 0 && (0.0..=1.0).contains(&fill_prob));
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let bitmap = (0..bitmap_size).map(|_| rng.gen_bool(fill_prob)).collect();
+        Self::from_bitvec(bitmap, k, seeds)
+    }
+
+    /// Create a filter of bitmap_size bits, for testing, with exactly `ones` distinct bits set
+    /// at random from rng_seed. Return `BloomError::TooManyOnes` if ones exceeds bitmap_size.
+    pub fn with_exact_ones(bitmap_size: u64, k: u64, seeds: (u64, u64), ones: u64, rng_seed: u64) -> Result<Self, BloomError> {
+        assert!(bitmap_size > 0);
+        if ones > bitmap_size {
+            return Err(BloomError::TooManyOnes);
+        }
+        let mut bloom = Self::from_bitvec(BitVec::from_elem(bitmap_size as usize, false), k, seeds);
+        bloom.set_random_bits(ones, &mut StdRng::seed_from_u64(rng_seed));
+        Ok(bloom)
+    }
+
+    /// Create a filter with the same parameters, insertions and number of set bits
+    /// as another, but with the bits placed at random from rng_seed.
+    /// The result has the statistical profile of the original without any of its items.
+    pub fn synthetic_like(other: &Bloom, rng_seed: u64) -> Self {
+        let mut bloom = Self::from_bitvec(BitVec::from_elem(other.bitmap.len(), false), other.k, other.seeds);
+        bloom.bitmap_size = other.bitmap_size;
+        bloom.insertions = other.insertions;
+        bloom.set_random_bits(other.ones, &mut StdRng::seed_from_u64(rng_seed));
+        bloom
+    }
+
     pub fn from_existing_struct(other: &Bloom) -> Self {
         Self {
-            bitmap: BitVec::from_bytes(other.bitmap().to_bytes().as_slice()),
+            bitmap: other.bitmap.clone(),
             bitmap_size: other.bitmap_size,
             k: other.k,
             seeds: other.seeds,
             xx: other.xx(),
+            insertions: other.insertions,
+            ones: other.ones,
+            root: other.root,
+            recent: other.recent.clone(),
+            hashers: other.hashers.clone(),
         }
     }
 
 
     /// Create a bloom filter structure with an existing state.
     /// The state is assumed to be retrieved from an existing bloom filter.
+    /// The bitmap is read MSB-first: bit 0 is the most significant bit of the first byte,
+    /// as produced by `bitmap().to_bytes()`.
+    /// The number of insertions that led to this state is unknown and starts at 0.
     pub fn from_existing(
         bitmap: &[u8],
         bitmap_size: u64,
@@ -123,174 +757,3443 @@ impl Bloom {
         seeds: (u64, u64)
     ) -> Self {
         let xx = (Self::xx_new(seeds.0), Self::xx_new(seeds.1));
+        let mut bitmap = BitVec::from_bytes(bitmap);
+        if bitmap.len() as u64 > bitmap_size {
+            bitmap.truncate(bitmap_size as usize);
+        }
+        let ones = Self::popcount(&bitmap);
         Self {
-            bitmap: BitVec::from_bytes(bitmap),
+            bitmap,
             bitmap_size,
             k,
             seeds,
             xx,
+            insertions: 0,
+            ones,
+            root: 0,
+            recent: None,
+            hashers: None,
         }
     }
 
-    /// Compute a recommended bitmap size for items_count items
-    /// and a fp_p rate of false positives.
-    /// fp_p has to be within the ]0.0, 1.0[ range.
-    pub fn compute_bitmap_size(items_count: usize, fp_p: f64) -> usize {
-        assert!(items_count > 0);
-        assert!(fp_p > 0.0 && fp_p < 1.0);
-        let log2 = f64::consts::LN_2;
-        let log2_2 = log2 * log2;
-        ((items_count as f64) * f64::ln(fp_p) / (-8.0 * log2_2)).ceil() as usize
+    /// Write the same format as `to_bytes` into `buffer` without allocating and return the
+    /// number of bytes written.
+    pub fn to_buffer(&self, buffer: &mut [u8]) -> Result<usize, BloomError> {
+        let len = BITMAP_OFFSET + self.bitmap.len().div_ceil(8);
+        if buffer.len() < len {
+            return Err(BloomError::BufferTooSmall);
+        }
+        buffer[..VERSION_OFFSET].copy_from_slice(&MAGIC);
+        buffer[VERSION_OFFSET] = FORMAT_VERSION;
+        buffer[SCHEME_OFFSET] = self.hash_scheme();
+        let values = [self.bitmap_size, self.k, self.seeds.0, self.seeds.1];
+        for (i, value) in values.iter().enumerate() {
+            let offset = BITMAP_SIZE_OFFSET + i * 8;
+            buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        let storage = self.bitmap.storage();
+        for (j, byte) in buffer[BITMAP_OFFSET..len].iter_mut().enumerate() {
+            *byte = ((storage[j / 4] >> ((j % 4) * 8)) as u8).reverse_bits();
+        }
+        Ok(len)
     }
 
-    fn bit_offset(&self, hashes: (u64, u64), i_k: u64) -> usize {
-        (self.double_hash(hashes, i_k) % self.bitmap_size) as usize
+    /// Serialize the filter: `MAGIC`, `FORMAT_VERSION` and `HASH_SCHEME`, or `CUSTOM_HASH_SCHEME`
+    /// with custom hashers, then bitmap_size, k and both seeds as little-endian u64s, then the
+    /// MSB-first bitmap bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BITMAP_OFFSET + self.bitmap.len().div_ceil(8));
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.hash_scheme());
+        for value in &[self.bitmap_size, self.k, self.seeds.0, self.seeds.1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.bitmap.to_bytes());
+        bytes
     }
 
-    /// Record the presence of an item.
-    pub fn add<T: Hash>(&mut self, item: &T) {
-        let hashes = self.hashes(item);
-//        let offsets = (0..self.k).map(|k| );
-        for i_k in 0..self.k {
-            let bit_offset = self.bit_offset(hashes, i_k);
-            self.bitmap.set(bit_offset, true);
+    fn hash_scheme(&self) -> u8 {
+        if self.hashers.is_some() {
+            CUSTOM_HASH_SCHEME
+        } else {
+            HASH_SCHEME
         }
     }
 
-    /// Check if an item is present in the set.
-    /// There can be false positives, but no false negatives.
-    pub fn check<T: Hash>(&self, item: &T) -> bool {
-        let hashes = self.hashes(item);
-        for i_k in 0..self.k {
-            let bit_offset = self.bit_offset(hashes, i_k);
-            if !self.bitmap.get(bit_offset).unwrap_or_else(|| panic!("bit_offset {} not in bitmap!", bit_offset)) {
-                return false;
-            }
+    /// Return `BloomError::CustomHashers` if the filter was built with `new_with_hashers`
+    fn check_serializable(&self) -> Result<(), BloomError> {
+        match self.hashers {
+            Some(_) => Err(BloomError::CustomHashers),
+            None => Ok(()),
         }
-        true
     }
 
-    /// Record the presence of an item in the set,
-    /// and return the previous state of this item.
-    pub fn check_and_add<T: Hash>(&mut self, item: &T) -> bool {
-        let hashes = self.hashes(item);
-        let mut found = true;
-        for i_k in 0..self.k {
-            let bit_offset = self.bit_offset(hashes, i_k);
-            if !self.bitmap.get(bit_offset).unwrap_or_else(|| panic!("bit_offset {} not in bitmap!", bit_offset)) {
-                found = false;
-                self.bitmap.set(bit_offset, true);
-            }
+    /// Return the layout of the format written by `to_bytes`
+    pub fn layout() -> BloomLayout {
+        BloomLayout {
+            magic: 0..VERSION_OFFSET,
+            version: VERSION_OFFSET..SCHEME_OFFSET,
+            scheme: SCHEME_OFFSET..BITMAP_SIZE_OFFSET,
+            bitmap_size: BITMAP_SIZE_OFFSET..K_OFFSET,
+            k: K_OFFSET..SEEDS_OFFSET,
+            seeds: SEEDS_OFFSET..BITMAP_OFFSET,
+            bitmap_offset: BITMAP_OFFSET,
         }
-        found
     }
 
-    /// Return the bitmap
-    pub fn bitmap(&self) -> BitVec {
-        self.bitmap.clone()
+    /// Create a bloom filter structure from bytes written by `to_bytes`.
+    /// A k of 0 or above `MAX_UNTRUSTED_K` is rejected with `BloomError::InvalidFormat`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bloom, BloomError> {
+        let (bitmap_size, k, seeds) = Self::parse_header(bytes)?;
+        let bitmap = &bytes[BITMAP_OFFSET..];
+        if bitmap.len() as u64 != bitmap_size.div_ceil(8) {
+            return Err(BloomError::InvalidFormat);
+        }
+        Ok(Bloom::from_existing(bitmap, bitmap_size, k, seeds))
     }
 
-    /// Return the number of bits in the filter
-    pub fn number_of_bits(&self) -> u64 {
-        self.bitmap_size
+    /// Panic unless the filter comes back equal from every serialization: `to_bytes`,
+    /// `to_buffer`, `SerdeBloom`, `to_delta_encoded` and `to_rle`, and from
+    /// `from_existing_struct`.
+    #[cfg(test)]
+    fn assert_roundtrip_stable(&self) {
+        assert!(Bloom::from_existing_struct(self) == *self, "from_existing_struct changed the filter");
+        let bytes = self.to_bytes();
+        assert!(Bloom::from_bytes(&bytes).as_ref() == Ok(self), "to_bytes round trip changed the filter");
+        let mut buffer = vec![0u8; bytes.len()];
+        assert_eq!(self.to_buffer(&mut buffer), Ok(bytes.len()));
+        assert!(buffer == bytes, "to_buffer doesn't match to_bytes");
+        let serde_bloom = SerdeBloom::try_from(self).unwrap();
+        assert!(Bloom::from(&serde_bloom) == *self, "serde round trip changed the filter");
+        let delta = Bloom::from_delta_encoded(&self.to_delta_encoded().unwrap());
+        assert!(delta.as_ref() == Ok(self), "delta encoding round trip changed the filter");
+        let rle = Bloom::from_rle(&self.to_rle(), self.bitmap_size, self.k, self.seeds);
+        assert!(rle.as_ref() == Ok(self), "run-length round trip changed the filter");
     }
 
-    /// Return the number of hash functions used for `check` and `set`
-    pub fn number_of_hash_functions(&self) -> u64 {
-        self.k
+    /// Return the format version of a blob serialized with `to_bytes`, checking only its
+    /// magic, so that blobs of any version can be sorted before being parsed.
+    pub fn peek_version(bytes: &[u8]) -> Result<u8, BloomError> {
+        if bytes.len() <= VERSION_OFFSET || bytes[..VERSION_OFFSET] != MAGIC {
+            return Err(BloomError::InvalidFormat);
+        }
+        Ok(bytes[VERSION_OFFSET])
     }
 
-    pub fn xx(&self) -> (XxHash64, XxHash64) {
-        self.xx
+    /// Return the size in bits and the number of hash functions of a filter serialized with
+    /// `to_bytes` by the upstream bloomfilter crate: a version byte, the bitmap length in
+    /// bytes as a little-endian u64, k as a little-endian u32, a 32 byte seed and the bitmap.
+    pub fn upstream_params(bytes: &[u8]) -> Result<(u64, u64), BloomError> {
+        if bytes.len() < UPSTREAM_HEADER_LEN {
+            return Err(BloomError::InvalidFormat);
+        }
+        if bytes[0] != UPSTREAM_VERSION {
+            return Err(BloomError::UnsupportedVersion(bytes[0]));
+        }
+        let mut len = [0u8; 8];
+        len.copy_from_slice(&bytes[1..9]);
+        let mut k = [0u8; 4];
+        k.copy_from_slice(&bytes[9..13]);
+        let (len, k) = (u64::from_le_bytes(len), u32::from_le_bytes(k));
+        if len == 0 || k == 0 || bytes.len() - UPSTREAM_HEADER_LEN != len as usize {
+            return Err(BloomError::InvalidFormat);
+        }
+        Ok((len * 8, u64::from(k)))
     }
 
-    fn optimal_k_num(bitmap_size: u64, items_count: usize) -> u64 {
-        let m = bitmap_size as f64;
-        let n = items_count as f64;
-        let k = (m / n * f64::ln(2.0f64)).ceil() as u64;
-        cmp::max(k, 1)
+    /// Import a filter serialized by the upstream bloomfilter crate. The bitmap size and k
+    /// translate, see `upstream_params`, but its 32 byte seed keys SipHash-1-3 rather than
+    /// XXHash64, so no item would be found at the same bits. A valid blob is thus rejected
+    /// with `BloomError::SchemeMismatch(UPSTREAM_HASH_SCHEME)` instead of giving a filter
+    /// with false negatives: rebuild it from the original items with the same parameters.
+    pub fn from_upstream(bytes: &[u8]) -> Result<Bloom, BloomError> {
+        Self::upstream_params(bytes)?;
+        Err(BloomError::SchemeMismatch(UPSTREAM_HASH_SCHEME))
     }
 
-    fn hash1<T: Hash>(&self, t: &T) -> u64 {
-        let mut hasher = self.xx().0;
-        t.hash(&mut hasher);
-        hasher.finish()
+    /// Read bitmap_size, k and the seeds from the header written by `to_bytes`
+    fn parse_header(bytes: &[u8]) -> Result<(u64, u64, (u64, u64)), BloomError> {
+        if bytes.len() < BITMAP_OFFSET || bytes[..VERSION_OFFSET] != MAGIC {
+            return Err(BloomError::InvalidFormat);
+        }
+        if bytes[VERSION_OFFSET] != FORMAT_VERSION {
+            return Err(BloomError::UnsupportedVersion(bytes[VERSION_OFFSET]));
+        }
+        if bytes[SCHEME_OFFSET] != HASH_SCHEME {
+            return Err(BloomError::SchemeMismatch(bytes[SCHEME_OFFSET]));
+        }
+        let read_u64 = |offset: usize| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&bytes[offset..offset + 8]);
+            u64::from_le_bytes(buf)
+        };
+        let bitmap_size = read_u64(BITMAP_SIZE_OFFSET);
+        if bitmap_size == 0 {
+            return Err(BloomError::InvalidFormat);
+        }
+        let k = read_u64(K_OFFSET);
+        if k == 0 || k > MAX_UNTRUSTED_K {
+            return Err(BloomError::InvalidFormat);
+        }
+        let seeds = (read_u64(SEEDS_OFFSET), read_u64(SEEDS_OFFSET + 8));
+        Ok((bitmap_size, k, seeds))
     }
 
-    fn hash2<T: Hash>(&self, t: &T) -> u64 {
-        let mut hasher = self.xx().1;
-        t.hash(&mut hasher);
-        hasher.finish()
+    /// Write the union of two compatible filters serialized with `to_bytes` to out,
+    /// in the same format, reading the bitmaps in chunks rather than loading either filter.
+    /// The insertions and content roots of the filters are not part of the format.
+    pub fn union_streams<R1: Read, R2: Read, W: Write>(a: &mut R1, b: &mut R2, out: &mut W) -> io::Result<()> {
+        let invalid = |err: BloomError| io::Error::new(io::ErrorKind::InvalidData, err);
+        let mut header_a = [0u8; BITMAP_OFFSET];
+        let mut header_b = [0u8; BITMAP_OFFSET];
+        a.read_exact(&mut header_a)?;
+        b.read_exact(&mut header_b)?;
+        let params = Self::parse_header(&header_a).map_err(invalid)?;
+        if Self::parse_header(&header_b).map_err(invalid)? != params {
+            return Err(invalid(BloomError::Incompatible));
+        }
+        out.write_all(&header_a)?;
+
+        let mut chunk_a = [0u8; 4096];
+        let mut chunk_b = [0u8; 4096];
+        let mut remaining = params.0.div_ceil(8) as usize;
+        while remaining > 0 {
+            let len = cmp::min(remaining, chunk_a.len());
+            a.read_exact(&mut chunk_a[..len])?;
+            b.read_exact(&mut chunk_b[..len])?;
+            for (byte_a, byte_b) in chunk_a[..len].iter_mut().zip(&chunk_b[..len]) {
+                *byte_a |= byte_b;
+            }
+            out.write_all(&chunk_a[..len])?;
+            remaining -= len;
+        }
+        Ok(())
     }
 
-    fn hashes<T: Hash>(&self, t: &T) -> (u64, u64) {
-        (self.hash1(t), self.hash2(t))
+    /// Return bitmap_size, k, the seeds and the MSB-first bitmap bytes as separate values,
+    /// to be stored as scalar and binary columns. `from_existing` loads them back.
+    /// Return `BloomError::CustomHashers` for filters built with `new_with_hashers`.
+    pub fn to_columns(&self) -> Result<Columns, BloomError> {
+        self.check_serializable()?;
+        Ok((self.bitmap_size, self.k, self.seeds, self.bitmap.to_bytes()))
     }
 
-    fn double_hash(&self, hashes: (u64, u64), i_k: u64) -> u64 {
-        hashes.0.wrapping_add(i_k.wrapping_mul(hashes.1)) % self.bitmap_size
+    /// Serialize bitmap_size, k and both seeds as little-endian u64s, followed by the gaps
+    /// between successive set bits as LEB128 varints. This is compact for sparse filters.
+    /// Return `BloomError::CustomHashers` for filters built with `new_with_hashers`.
+    pub fn to_delta_encoded(&self) -> Result<Vec<u8>, BloomError> {
+        self.check_serializable()?;
+        let mut bytes = Vec::with_capacity(32 + self.ones as usize);
+        for value in &[self.bitmap_size, self.k, self.seeds.0, self.seeds.1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut next = 0;
+        for (offset, _) in self.bitmap.iter().enumerate().filter(|&(_, bit)| bit) {
+            let mut gap = (offset - next) as u64;
+            while gap >= 0x80 {
+                bytes.push(gap as u8 | 0x80);
+                gap >>= 7;
+            }
+            bytes.push(gap as u8);
+            next = offset + 1;
+        }
+        Ok(bytes)
     }
 
-    /// Clear all of the bits in the filter, removing all keys from the set
-    pub fn clear(&mut self) {
-        self.bitmap.clear()
+    /// Rebuild a filter serialized with `to_delta_encoded`. A bitmap_size above
+    /// `MAX_UNTRUSTED_BITS` is rejected with `BloomError::InvalidFormat`.
+    pub fn from_delta_encoded(bytes: &[u8]) -> Result<Bloom, BloomError> {
+        if bytes.len() < 32 {
+            return Err(BloomError::InvalidFormat);
+        }
+        let mut params = [0u64; 4];
+        for (i, param) in params.iter_mut().enumerate() {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *param = u64::from_le_bytes(buf);
+        }
+        let [bitmap_size, k, seed0, seed1] = params;
+        let len = Self::untrusted_bitmap_len(bitmap_size).ok_or(BloomError::InvalidFormat)?;
+        let mut bitmap = BitVec::from_elem(len, false);
+        let (mut next, mut gap, mut shift) = (0u64, 0u64, 0);
+        for &byte in &bytes[32..] {
+            if shift >= 64 {
+                return Err(BloomError::InvalidFormat);
+            }
+            gap |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 != 0 {
+                continue;
+            }
+            let offset = next.checked_add(gap).filter(|&offset| offset < bitmap_size);
+            let offset = offset.ok_or(BloomError::InvalidFormat)?;
+            bitmap.set(offset as usize, true);
+            next = offset + 1;
+            gap = 0;
+            shift = 0;
+        }
+        if shift != 0 {
+            return Err(BloomError::InvalidFormat);
+        }
+        Ok(Self::from_bitvec(bitmap, k, (seed0, seed1)))
     }
 
-    fn xx_new(seed: u64) -> XxHash64 {
-        XxHash64::with_seed(seed)
+    /// Return the bitmap as runs of equal bits, from the first bit on.
+    pub fn to_rle(&self) -> Vec<(bool, u64)> {
+        let mut runs: Vec<(bool, u64)> = Vec::new();
+        for bit in self.bitmap.iter() {
+            match runs.last_mut() {
+                Some(&mut (value, ref mut len)) if value == bit => *len += 1,
+                _ => runs.push((bit, 1)),
+            }
+        }
+        runs
     }
-}
 
-#[test]
-fn bloom_test_add() {
-    let mut bloom = Bloom::new(100, 10);
-    let key: u64 = rand::random();
-    assert_eq!(bloom.check(&key), false);
-    bloom.add(&key);
-    assert_eq!(bloom.check(&key), true);
-}
+    /// Create a filter by expanding runs of equal bits, as returned by `to_rle`, into its
+    /// bitmap. Return `BloomError::SizeMismatch` unless the runs add up to bitmap_size bits,
+    /// and `BloomError::InvalidFormat` if bitmap_size is above `MAX_UNTRUSTED_BITS`.
+    pub fn from_rle(rle: &[(bool, u64)], bitmap_size: u64, k: u64, seeds: (u64, u64)) -> Result<Bloom, BloomError> {
+        let total = rle.iter().try_fold(0u64, |total, &(_, len)| total.checked_add(len));
+        if bitmap_size == 0 || total != Some(bitmap_size) {
+            return Err(BloomError::SizeMismatch);
+        }
+        let len = Self::untrusted_bitmap_len(bitmap_size).ok_or(BloomError::InvalidFormat)?;
+        let mut bitmap = BitVec::with_capacity(len);
+        for &(value, len) in rle {
+            for _ in 0..len {
+                bitmap.push(value);
+            }
+        }
+        Ok(Self::from_bitvec(bitmap, k, seeds))
+    }
 
-#[test]
+    /// Return bitmap_size as a bitmap length if it is positive and at most `MAX_UNTRUSTED_BITS`
+    fn untrusted_bitmap_len(bitmap_size: u64) -> Option<usize> {
+        if bitmap_size == 0 || bitmap_size > MAX_UNTRUSTED_BITS {
+            return None;
+        }
+        usize::try_from(bitmap_size).ok()
+    }
+
+    /// Write the parameters and the bitmap of the filter to separate streams.
+    /// meta receives bitmap_size, k and both seeds as little-endian u64s,
+    /// bitmap receives the raw bitmap bytes. Fails with `io::ErrorKind::InvalidInput` for
+    /// filters built with `new_with_hashers`.
+    pub fn to_split<W1: Write, W2: Write>(&self, meta: &mut W1, bitmap: &mut W2) -> io::Result<()> {
+        self.check_serializable()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        for value in &[self.bitmap_size, self.k, self.seeds.0, self.seeds.1] {
+            meta.write_all(&value.to_le_bytes())?;
+        }
+        bitmap.write_all(&self.bitmap.to_bytes())
+    }
+
+    /// Create a bloom filter structure from parameters and bitmap bytes
+    /// read from separate streams, as written by `to_split`.
+    pub fn from_split<R1: Read, R2: Read>(meta: &mut R1, bitmap: &mut R2) -> io::Result<Bloom> {
+        let bitmap_size = Self::read_u64(meta)?;
+        let k = Self::read_u64(meta)?;
+        let seeds = (Self::read_u64(meta)?, Self::read_u64(meta)?);
+        let mut bytes = Vec::new();
+        bitmap.read_to_end(&mut bytes)?;
+        if bitmap_size == 0 || bytes.len() as u64 != bitmap_size.div_ceil(8) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bitmap length doesn't match bitmap_size",
+            ));
+        }
+        Ok(Bloom::from_existing(&bytes, bitmap_size, k, seeds))
+    }
+
+    fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Return the bitmap bytes LSB-first: bit 0 is the least significant bit of the first byte.
+    /// This is the reverse, within each byte, of the MSB-first `bitmap().to_bytes()`.
+    pub fn to_bytes_lsb_first(&self) -> Vec<u8> {
+        self.bitmap.to_bytes().iter().map(|byte| byte.reverse_bits()).collect()
+    }
+
+    /// Create a bloom filter structure with an existing state,
+    /// reading the bitmap LSB-first as produced by `to_bytes_lsb_first`.
+    pub fn from_bytes_lsb_first(bitmap: &[u8], bitmap_size: u64, k: u64, seeds: (u64, u64)) -> Self {
+        let bitmap: Vec<u8> = bitmap.iter().map(|byte| byte.reverse_bits()).collect();
+        Bloom::from_existing(&bitmap, bitmap_size, k, seeds)
+    }
+
+    /// Compute a recommended bitmap size for items_count items
+    /// and a fp_p rate of false positives.
+    /// fp_p has to be within the ]0.0, 1.0[ range.
+    pub fn compute_bitmap_size(items_count: usize, fp_p: f64) -> usize {
+        assert!(items_count > 0);
+        assert!(fp_p > 0.0 && fp_p < 1.0);
+        let log2 = f64::consts::LN_2;
+        let log2_2 = log2 * log2;
+        ((items_count as f64) * f64::ln(fp_p) / (-8.0 * log2_2)).ceil() as usize
+    }
+
+    fn bit_offset(&self, hashes: (u64, u64), i_k: u64) -> usize {
+        (self.double_hash(hashes, i_k) % self.bitmap_size) as usize
+    }
+
+    /// Return the bit offsets probed for an item, one per hash function, in probe order.
+    /// The same offset can appear more than once.
+    pub fn probe_offsets<T: Hash>(&self, item: &T) -> Vec<usize> {
+        let hashes = self.hashes(item);
+        (0..self.k).map(|i_k| self.bit_offset(hashes, i_k)).collect()
+    }
+
+    /// Return the distinct bit offsets probed for an item, sorted.
+    pub fn unique_offsets<T: Hash>(&self, item: &T) -> Vec<usize> {
+        let mut offsets = self.probe_offsets(item);
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Return the number of distinct bits probed for an item, which is lower than
+    /// the number of hash functions when double hashing lands on the same bit twice.
+    pub fn effective_k<T: Hash>(&self, item: &T) -> u64 {
+        self.unique_offsets(item).len() as u64
+    }
+
+    /// Probe n random keys generated from rng_seed and return, for each of the k hash
+    /// functions, the fraction of keys for which it hit a bit already probed by a
+    /// lower-index function for the same key. The filter itself is left untouched.
+    pub fn hash_function_bias(&self, n: usize, rng_seed: u64) -> Vec<f64> {
+        assert!(n > 0);
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let mut collisions = vec![0usize; self.k as usize];
+        for _ in 0..n {
+            let offsets = self.probe_offsets(&rng.gen::<u64>());
+            for (i, offset) in offsets.iter().enumerate() {
+                if offsets[..i].contains(offset) {
+                    collisions[i] += 1;
+                }
+            }
+        }
+        collisions.iter().map(|&count| count as f64 / n as f64).collect()
+    }
+
+    fn effective_k_variance(&self, sample: usize, rng_seed: u64) -> f64 {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let values: Vec<f64> = (0..sample).map(|_| self.effective_k(&rng.gen::<u64>()) as f64).collect();
+        let mean = values.iter().sum::<f64>() / sample as f64;
+        values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / sample as f64
+    }
+
+    /// Return the step between the successive bits probed for an item, its second base hash
+    /// modulo the number of bits, ignoring the wraparound of the 64-bit sums. A step of zero,
+    /// or sharing a large factor with the number of bits, makes the item probe few distinct bits.
+    pub fn hash_increment<T: Hash>(&self, item: &T) -> u64 {
+        self.hashes(item).1 % self.bitmap_size
+    }
+
+    /// Return the pair of base hashes of an item, from which its bit offsets are derived
+    pub fn hash_pair<T: Hash>(&self, item: &T) -> (u64, u64) {
+        self.hashes(item)
+    }
+
+    /// Return the smallest number of hash functions for which the bits probed for a and b
+    /// differ, using this filter's size and seeds, or `None` if they don't differ for
+    /// any number up to 64 times the filter's own.
+    pub fn min_k_to_distinguish<T: Hash>(&self, a: &T, b: &T) -> Option<u64> {
+        let (hashes_a, hashes_b) = (self.hashes(a), self.hashes(b));
+        if hashes_a == hashes_b {
+            return None;
+        }
+        let (mut probes_a, mut probes_b) = (HashSet::new(), HashSet::new());
+        // Number of offsets probed for only one of the keys
+        let mut unmatched = 0i64;
+        for i_k in 0..cmp::max(self.k, 1) * 64 {
+            let (offset_a, offset_b) = (self.bit_offset(hashes_a, i_k), self.bit_offset(hashes_b, i_k));
+            if probes_a.insert(offset_a) {
+                unmatched += if probes_b.contains(&offset_a) { -1 } else { 1 };
+            }
+            if probes_b.insert(offset_b) {
+                unmatched += if probes_a.contains(&offset_b) { -1 } else { 1 };
+            }
+            if unmatched > 0 {
+                return Some(i_k + 1);
+            }
+        }
+        None
+    }
+
+    /// Record the presence of an item.
+    pub fn add<T: Hash>(&mut self, item: &T) {
+        let hashes = self.hashes(item);
+        self.add_hashes(hashes);
+    }
+
+    fn add_hashes(&mut self, hashes: (u64, u64)) {
+        let k = self.k;
+        self.add_hashes_with_k(hashes, k);
+    }
+
+    fn add_hashes_with_k(&mut self, hashes: (u64, u64), k: u64) {
+        for i_k in 0..k {
+            let bit_offset = self.bit_offset(hashes, i_k);
+            self.set_bit(bit_offset);
+        }
+        self.record_insertion(hashes);
+    }
+
+    /// Record the presence of an item unless the filter already reached its design
+    /// false positive rate of 0.5^k. That rate is reached when half of the bits are set,
+    /// which is where a filter sized with `new` or `new_with_rate` is at capacity.
+    /// Items already reported present are not inserted again.
+    pub fn add_throttled<T: Hash>(&mut self, item: &T) -> AddOutcome {
+        if self.check(item) {
+            AddOutcome::AlreadyPresent
+        } else if self.current_fp_rate() > 0.5f64.powf(self.k as f64) {
+            AddOutcome::ThrottledWouldExceedFp
+        } else {
+            self.add(item);
+            AddOutcome::Inserted
+        }
+    }
+
+    /// Record the presence of every integer in a range.
+    pub fn add_range(&mut self, range: Range<u64>) {
+        for item in range {
+            self.add(&item);
+        }
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T: Hash>(&self, item: &T) -> bool {
+        self.check_hashes(self.hashes(item))
+    }
+
+    fn check_hashes(&self, hashes: (u64, u64)) -> bool {
+        self.check_hashes_with_k(hashes, self.k)
+    }
+
+    fn check_hashes_with_k(&self, hashes: (u64, u64), k: u64) -> bool {
+        for i_k in 0..k {
+            let bit_offset = self.bit_offset(hashes, i_k);
+            if !self.bitmap.get(bit_offset).unwrap_or_else(|| panic!("bit_offset {} not in bitmap!", bit_offset)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Record the presence of an item using k hash functions instead of the filter's own.
+    /// A larger k gives this item a lower false positive rate under `check_with_k` with the
+    /// same k, at the cost of filling the bitmap faster for everyone. Once items use
+    /// different values of k, `current_fp_rate` and the other estimates assuming a single k
+    /// are only approximate.
+    pub fn add_with_k<T: Hash>(&mut self, item: &T, k: u64) {
+        assert!(k > 0);
+        let hashes = self.hashes(item);
+        self.add_hashes_with_k(hashes, k);
+    }
+
+    /// Check if an item recorded with `add_with_k` is present, probing k bits. Items added
+    /// with fewer than k hash functions might not be found.
+    pub fn check_with_k<T: Hash>(&self, item: &T, k: u64) -> bool {
+        assert!(k > 0);
+        self.check_hashes_with_k(self.hashes(item), k)
+    }
+
+    /// Record the presence of an item only for a sample_rate fraction of items, picked from
+    /// their hash so that a given item is always either kept or skipped. Return whether the
+    /// item was recorded. Skipped items are false negatives, see
+    /// `expected_false_negative_rate`.
+    pub fn add_sampled<T: Hash>(&mut self, item: &T, sample_rate: f64) -> bool {
+        assert!((0.0..=1.0).contains(&sample_rate));
+        let hashes = self.hashes(item);
+        let sampled = (hashes.0 as f64) < sample_rate * u64::MAX as f64;
+        if sampled {
+            self.add_hashes(hashes);
+        }
+        sampled
+    }
+
+    /// Return the fraction of items passed to `add_sampled` with sample_rate that `check`
+    /// misses, 1 - sample_rate: sampling trades recall for space.
+    pub fn expected_false_negative_rate(&self, sample_rate: f64) -> f64 {
+        assert!((0.0..=1.0).contains(&sample_rate));
+        1.0 - sample_rate
+    }
+
+    /// Record the presence of all the items if none of them is already present, and return
+    /// whether they were recorded. Otherwise the filter is left unchanged. A false positive
+    /// on any item rejects the whole batch even though none of it was recorded before.
+    pub fn add_batch_if_all_absent<T: Hash>(&mut self, items: &[T]) -> bool {
+        let hashes: Vec<(u64, u64)> = items.iter().map(|item| self.hashes(item)).collect();
+        if hashes.iter().any(|&hashes| self.check_hashes(hashes)) {
+            return false;
+        }
+        for hashes in hashes {
+            self.add_hashes(hashes);
+        }
+        true
+    }
+
+    /// Record the presence of an item in the set,
+    /// and return the previous state of this item.
+    pub fn check_and_add<T: Hash>(&mut self, item: &T) -> bool {
+        let hashes = self.hashes(item);
+        let mut found = true;
+        for i_k in 0..self.k {
+            let bit_offset = self.bit_offset(hashes, i_k);
+            if !self.bitmap.get(bit_offset).unwrap_or_else(|| panic!("bit_offset {} not in bitmap!", bit_offset)) {
+                found = false;
+                self.set_bit(bit_offset);
+            }
+        }
+        if !found {
+            self.record_insertion(hashes);
+        }
+        found
+    }
+
+    /// Run an operation through `add`, `check` or `check_and_add`.
+    pub fn apply<T: Hash>(&mut self, op: BloomOp<T>) -> BloomResult {
+        match op {
+            BloomOp::Add(item) => {
+                self.add(&item);
+                BloomResult::Added
+            }
+            BloomOp::Check(item) => BloomResult::Checked(self.check(&item)),
+            BloomOp::CheckAndAdd(item) => BloomResult::CheckedAndAdded(self.check_and_add(&item)),
+        }
+    }
+
+    /// Return the items that are not present in the set.
+    /// Because of false positives, some absent items may be left out.
+    pub fn filter_absent<'a, T: Hash>(&self, items: &'a [T]) -> Vec<&'a T> {
+        self.absent_iter(items.iter()).collect()
+    }
+
+    /// Lazily yield the items of `iter` that are not present in the set,
+    /// without collecting them first.
+    pub fn absent_iter<'a, I>(&'a self, iter: I) -> impl Iterator<Item = I::Item> + 'a
+    where
+        I: Iterator + 'a,
+        I::Item: Hash,
+    {
+        iter.filter(move |item| !self.check(item))
+    }
+
+    /// Return the fraction of items reported absent, i.e. that adding them would record
+    /// something new. An empty batch has a novelty fraction of 0.0.
+    pub fn novelty_fraction<T: Hash>(&self, items: &[T]) -> f64 {
+        if items.is_empty() {
+            return 0.0;
+        }
+        self.absent_iter(items.iter()).count() as f64 / items.len() as f64
+    }
+
+    /// Check that all of the keys are present in the set, returning the indices of
+    /// those reported absent. Since there are no false negatives, any such index
+    /// means the filter lost keys it was given, e.g. through corruption.
+    pub fn assert_contains_all<T: Hash>(&self, keys: &[T]) -> Result<(), Vec<usize>> {
+        let missing: Vec<usize> = keys
+            .iter()
+            .enumerate()
+            .filter(|&(_, key)| !self.check(key))
+            .map(|(i, _)| i)
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// Build a new filter holding only the candidates this filter reports present,
+    /// sized for them and agreeing with this filter on every candidate.
+    /// Seeds are drawn again until none of the rejected candidates is a false positive,
+    /// at most `RESTRICT_MAX_ATTEMPTS` times; each draw succeeds with probability about 0.99.
+    /// If every draw fails, the one with the fewest false positives is returned, which then
+    /// reports some rejected candidates present.
+    pub fn restrict_to<T: Hash>(&self, candidates: &[T]) -> Bloom {
+        let (accepted, rejected): (Vec<&T>, Vec<&T>) = candidates.iter().partition(|item| self.check(item));
+        let fp_p = 0.01 / cmp::max(rejected.len(), 1) as f64;
+        let mut best: Option<(usize, Bloom)> = None;
+        for _ in 0..RESTRICT_MAX_ATTEMPTS {
+            let mut restricted = Bloom::new_with_rate(cmp::max(accepted.len(), 1), fp_p);
+            for item in &accepted {
+                restricted.add(item);
+            }
+            let false_positives = rejected.iter().filter(|item| restricted.check(item)).count();
+            if false_positives == 0 {
+                return restricted;
+            }
+            if best.as_ref().is_none_or(|&(fewest, _)| false_positives < fewest) {
+                best = Some((false_positives, restricted));
+            }
+        }
+        best.unwrap().1
+    }
+
+    /// Return the bitmap
+    pub fn bitmap(&self) -> BitVec {
+        self.bitmap.clone()
+    }
+
+    /// Replace the bitmap with another one built for the same size, number of hash functions
+    /// and seeds, returning the old bitmap. new_size is the number of bits of the new bitmap.
+    /// The insertions, content root and recent inserts are reset since they described the
+    /// old bitmap.
+    pub fn swap_bitmap(&mut self, new: BitVec, new_size: u64) -> Result<BitVec, BloomError> {
+        if new_size != self.bitmap_size || new.len() as u64 != new_size {
+            return Err(BloomError::SizeMismatch);
+        }
+        self.ones = Self::popcount(&new);
+        self.insertions = 0;
+        self.root = 0;
+        self.clear_recent_inserts();
+        Ok(mem::replace(&mut self.bitmap, new))
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bitmap_size
+    }
+
+    /// Return the number of insertions recorded by `add` and `check_and_add`,
+    /// including insertions of items that were already present
+    pub fn insertions(&self) -> u64 {
+        self.insertions
+    }
+
+    /// Return a digest of the items recorded by `add` and `check_and_add`, combining
+    /// the hashes of each insertion with XOR so that it doesn't depend on their order.
+    /// Two filters fed the same insertions, in any order, have the same root.
+    /// Inserting an item an even number of times cancels it out.
+    pub fn content_root(&self) -> u64 {
+        self.root
+    }
+
+    /// Return the number of bits set in the filter
+    pub fn count_ones(&self) -> u64 {
+        self.ones
+    }
+
+    /// Estimate the number of distinct cache lines of cache_line_bytes bytes touched by the
+    /// k probes of a lookup for a present item. With L lines and probes spread uniformly,
+    /// this is L * (1 - (1 - 1/L)^k): close to k for large filters, while a blocked layout
+    /// keeping all of an item's bits in one line always touches a single line.
+    pub fn cache_miss_estimate(&self, cache_line_bytes: usize) -> f64 {
+        assert!(cache_line_bytes > 0);
+        let lines = self.bitmap_size.div_ceil(cache_line_bytes as u64 * 8) as f64;
+        lines * (1.0 - (1.0 - 1.0 / lines).powf(self.k as f64))
+    }
+
+    /// Estimate the CPU cycles a `check` of a present item takes: hashing, k probes, and
+    /// miss_penalty_cycles for each of the `cache_miss_estimate` lines missing from a 1 MiB
+    /// cache, which holds a fraction 1 MiB / size of a larger filter's lines.
+    pub fn estimated_query_cycles(&self, cache_line_bytes: usize, miss_penalty_cycles: u64) -> f64 {
+        let bytes = self.bitmap_size.div_ceil(8) as f64;
+        let miss_ratio = (1.0 - CACHE_BYTES / bytes).max(0.0);
+        let misses = self.cache_miss_estimate(cache_line_bytes) * miss_ratio;
+        HASH_CYCLES + self.k as f64 * PROBE_CYCLES + misses * miss_penalty_cycles as f64
+    }
+
+    /// Return the fill ratio of each of regions equal-width regions of the bitmap,
+    /// the last one holding the remaining bits.
+    pub fn fill_histogram(&self, regions: usize) -> Vec<f64> {
+        assert!(regions > 0);
+        let len = self.bitmap.len();
+        let width = cmp::max(len.div_ceil(regions), 1);
+        (0..len)
+            .step_by(width)
+            .map(|start| {
+                let end = cmp::min(start + width, len);
+                let ones = (start..end).filter(|&i| self.bitmap[i]).count();
+                ones as f64 / (end - start) as f64
+            })
+            .collect()
+    }
+
+    /// Return the number of bits set in each 64-bit word of the bitmap, the last word
+    /// holding the remaining bits when the size isn't a multiple of 64.
+    pub fn word_popcounts(&self) -> Vec<u32> {
+        self.bitmap
+            .storage()
+            .chunks(2)
+            .map(|blocks| blocks.iter().map(|block| block.count_ones()).sum())
+            .collect()
+    }
+
+    /// Estimate the size of the bitmap after compression as a fraction of its raw size,
+    /// from 0.0 (highly compressible) to 1.0 (incompressible).
+    /// 64-bit words that are all zeros or all ones are counted as one bit each, as runs,
+    /// and the other words at the binary entropy of their fill ratio.
+    pub fn compressibility(&self) -> f64 {
+        let len = self.bitmap.len() as u64;
+        let popcounts = self.word_popcounts();
+        let (mut run_words, mut mixed_bits, mut mixed_ones) = (0u64, 0u64, 0u64);
+        for (i, &ones) in popcounts.iter().enumerate() {
+            let bits = cmp::min(64, len - 64 * i as u64);
+            let ones = u64::from(ones);
+            if ones == 0 || ones == bits {
+                run_words += 1;
+            } else {
+                mixed_bits += bits;
+                mixed_ones += ones;
+            }
+        }
+        let mixed = match mixed_bits {
+            0 => 0.0,
+            bits => bits as f64 * Self::binary_entropy(mixed_ones as f64 / bits as f64),
+        };
+        ((run_words as f64 + mixed) / len as f64).min(1.0)
+    }
+
+    /// Return the fewest bits any encoding needs on average for a bitmap with this fill
+    /// ratio p, m * H(p) with H the binary entropy, to compare with the serialized size.
+    pub fn optimal_serialized_bits(&self) -> f64 {
+        self.bitmap_size as f64 * Self::binary_entropy(self.fill_ratio())
+    }
+
+    fn binary_entropy(p: f64) -> f64 {
+        if p <= 0.0 || p >= 1.0 {
+            return 0.0;
+        }
+        -p * p.log2() - (1.0 - p) * (1.0 - p).log2()
+    }
+
+    /// Return the offset of a set bit picked uniformly at random, or `None` if the filter is empty.
+    /// When at least an eighth of the bits are set, random offsets are drawn until one is set,
+    /// falling back after 64 misses, or directly on sparser filters, to walking the words
+    /// with the cached ones count to find a set bit of random rank.
+    pub fn sample_set_bit<R: Rng>(&self, rng: &mut R) -> Option<usize> {
+        if self.ones == 0 {
+            return None;
+        }
+        if self.ones * 8 >= self.bitmap.len() as u64 {
+            for _ in 0..64 {
+                let offset = rng.gen_range(0, self.bitmap.len());
+                if self.bitmap.get(offset) == Some(true) {
+                    return Some(offset);
+                }
+            }
+        }
+        let mut rank = rng.gen_range(0, self.ones);
+        for (i, word) in self.bitmap.storage().iter().enumerate() {
+            let word_ones = u64::from(word.count_ones());
+            if rank >= word_ones {
+                rank -= word_ones;
+                continue;
+            }
+            let mut word = *word;
+            for _ in 0..rank {
+                word &= word - 1;
+            }
+            return Some(i * 32 + word.trailing_zeros() as usize);
+        }
+        unreachable!("ones count out of sync with the bitmap")
+    }
+
+    /// Return the fraction of bits set in the filter
+    pub fn fill_ratio(&self) -> f64 {
+        self.count_ones() as f64 / self.bitmap_size as f64
+    }
+
+    /// Return the current false positive rate, the probability that the k bits
+    /// probed for an absent item are all set at the current fill ratio.
+    pub fn current_fp_rate(&self) -> f64 {
+        self.fill_ratio().powf(self.k as f64)
+    }
+
+    /// Return whether the filter is empty, saturated or in between
+    pub fn health(&self) -> Health {
+        if self.ones == 0 {
+            Health::Empty
+        } else if self.current_fp_rate() >= 0.99 {
+            Health::Saturated
+        } else {
+            Health::Normal
+        }
+    }
+
+    /// Return the expected number of false positives among negative_queries lookups
+    /// for absent items, at the current false positive rate.
+    pub fn expected_false_positives(&self, negative_queries: u64) -> f64 {
+        negative_queries as f64 * self.current_fp_rate()
+    }
+
+    /// Return the correlation between the false positives of this filter and other's over
+    /// `sample` random keys, assumed absent from both. Values near 0 mean chaining the two
+    /// filters multiplies their false positive rates.
+    pub fn fp_independence(&self, other: &Bloom, sample: usize, rng_seed: u64) -> f64 {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let (mut hits_a, mut hits_b, mut hits_both) = (0.0, 0.0, 0.0);
+        for _ in 0..sample {
+            let key = rng.gen::<u64>();
+            let (hit_a, hit_b) = (self.check(&key), other.check(&key));
+            hits_a += hit_a as u8 as f64;
+            hits_b += hit_b as u8 as f64;
+            hits_both += (hit_a && hit_b) as u8 as f64;
+        }
+        let n = sample as f64;
+        let (p_a, p_b) = (hits_a / n, hits_b / n);
+        let variance = p_a * (1.0 - p_a) * p_b * (1.0 - p_b);
+        if variance == 0.0 {
+            return 0.0;
+        }
+        (hits_both / n - p_a * p_b) / variance.sqrt()
+    }
+
+    /// Simulate an attacker who knows the seeds and the bitmap, and so can compute offline
+    /// which keys the filter accepts, and return the false positive rate achieved over
+    /// attacker_queries lookups. For each lookup the attacker tries up to 1000 random keys
+    /// generated from rng_seed, assumed absent, and submits the first one accepted.
+    /// The result is close to 1.0 for any filter that isn't nearly empty: exposed seeds
+    /// void the false positive rate guarantees.
+    pub fn adversarial_fp_estimate(&self, attacker_queries: u64, rng_seed: u64) -> f64 {
+        assert!(attacker_queries > 0);
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let false_positives = (0..attacker_queries)
+            .filter(|_| (0..1_000).any(|_| self.check(&rng.gen::<u64>())))
+            .count();
+        false_positives as f64 / attacker_queries as f64
+    }
+
+    /// Estimate the number of distinct items recorded in the filter from its number of set bits,
+    /// using n = -(m / k) * ln(1 - X / m). A saturated filter is estimated at `u64::MAX`.
+    pub fn estimate_count(&self) -> u64 {
+        Self::count_from_ones(self.bitmap_size, self.k, self.ones as f64).round() as u64
+    }
+
+    /// Return `estimate_count` divided by its mean over the other filters, above 1 for a
+    /// filter more loaded than its peers. Peers with no items make any load infinite.
+    /// With no peers the filter is only compared to itself and 1.0 is returned.
+    pub fn relative_load(&self, others: &[&Bloom]) -> f64 {
+        if others.is_empty() {
+            return 1.0;
+        }
+        let mean = others.iter().map(|other| other.estimate_count() as f64).sum::<f64>() / others.len() as f64;
+        let own = self.estimate_count() as f64;
+        if mean == 0.0 {
+            return if own == 0.0 { 1.0 } else { f64::INFINITY };
+        }
+        own / mean
+    }
+
+    /// Return how many items an empty filter like this one can hold with its actual number
+    /// of hash functions before its false positive rate exceeds target_fp. Since that number
+    /// is rounded up from the optimum, this can be below the items_count it was created for.
+    pub fn effective_capacity(&self, target_fp: f64) -> u64 {
+        assert!(target_fp > 0.0 && target_fp < 1.0);
+        let max_fill = target_fp.powf(1.0 / self.k as f64);
+        Self::count_from_ones(self.bitmap_size, self.k, max_fill * self.bitmap_size as f64).floor() as u64
+    }
+
+    /// Return the size in bytes and the number of hash functions of a replacement filter
+    /// holding observed_items items at target_fp, for a filter whose load turned out to
+    /// differ from what it was created for. This ignores the current filter's parameters.
+    pub fn suggest_next_config(&self, observed_items: u64, target_fp: f64) -> (usize, u64) {
+        assert!(observed_items > 0);
+        let bytes = Self::compute_bitmap_size(observed_items as usize, target_fp);
+        (bytes, Self::optimal_k_num(bytes as u64 * 8, observed_items as usize))
+    }
+
+    /// Return how many more distinct items can be inserted, judging from the current fill,
+    /// before the false positive rate exceeds max_fp.
+    pub fn items_until_fp(&self, max_fp: f64) -> u64 {
+        assert!(max_fp > 0.0 && max_fp < 1.0);
+        let max_fill = max_fp.powf(1.0 / self.k as f64);
+        let capacity = Self::count_from_ones(self.bitmap_size, self.k, max_fill * self.bitmap_size as f64);
+        let current = Self::count_from_ones(self.bitmap_size, self.k, self.ones as f64);
+        (capacity - current).max(0.0).floor() as u64
+    }
+
+    /// Return how long the filter can take insert_rate_per_sec new items, starting from its
+    /// current fill, before the false positive rate exceeds max_fp. On an empty filter this
+    /// is the rotation interval that keeps every generation under that bound.
+    /// Rates so low that the interval overflows a `Duration` give `Duration::MAX`.
+    pub fn optimal_rotation_interval(&self, insert_rate_per_sec: f64, max_fp: f64) -> Duration {
+        assert!(insert_rate_per_sec > 0.0);
+        let secs = self.items_until_fp(max_fp) as f64 / insert_rate_per_sec;
+        Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+    }
+
+    /// Return the number of bits of the filter per item recorded, based on `estimate_count`,
+    /// or `None` if the filter is empty.
+    pub fn current_bits_per_item(&self) -> Option<f64> {
+        match self.estimate_count() {
+            0 => None,
+            count => Some(self.bitmap_size as f64 / count as f64),
+        }
+    }
+
+    /// Return how many more bits than this filter a replacement needs to hold extra_items
+    /// more items at the same false positive rate, i.e. at the same `current_bits_per_item`.
+    /// An empty filter has no measured load and returns 0.
+    pub fn additional_bits_for(&self, extra_items: usize) -> u64 {
+        match self.current_bits_per_item() {
+            Some(bits_per_item) => (bits_per_item * extra_items as f64).ceil() as u64,
+            None => 0,
+        }
+    }
+
+    /// Return the order in which to fold filters of the given sizes into an accumulator so
+    /// that peak memory is lowest, when filters are loaded one at a time. Folding into an
+    /// accumulator at least as large as the next filter happens in place, anything else
+    /// materializes a new accumulator, so the order is largest first: the accumulator never
+    /// grows and the peak is the sum of the two largest sizes.
+    pub fn plan_merge_order(sizes: &[usize]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&i| cmp::Reverse(sizes[i]));
+        order
+    }
+
+    /// Union every filter of a sliding window into a new filter, or return
+    /// `BloomError::Incompatible` if the window is empty or its filters don't share parameters.
+    pub fn window_union(windows: &VecDeque<Bloom>) -> Result<Bloom, BloomError> {
+        let mut filters = windows.iter();
+        let mut union = match filters.next() {
+            Some(first) => Self::from_existing_struct(first),
+            None => return Err(BloomError::Incompatible),
+        };
+        for other in filters {
+            union.union(other)?;
+        }
+        Ok(union)
+    }
+
+    /// Estimate the number of distinct items recorded in any of the filters, which must be
+    /// compatible, by applying `estimate_count` to the union of their bitmaps.
+    pub fn distinct_estimate_across(filters: &[&Bloom]) -> Result<u64, BloomError> {
+        let (first, rest) = match filters.split_first() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        let mut union = first.bitmap.clone();
+        for other in rest {
+            if !first.is_compatible(other) {
+                return Err(BloomError::Incompatible);
+            }
+            union.or(&other.bitmap);
+        }
+        let ones = Self::popcount(&union);
+        Ok(Self::count_from_ones(first.bitmap_size, first.k, ones as f64).round() as u64)
+    }
+
+    /// Return a lower bound on the number of distinct items recorded in the filter,
+    /// holding with probability confidence, in ]0.0, 1.0[.
+    /// Each bit is modelled as set independently with probability X / m, so the number of
+    /// set bits X has a binomial variance of X * (1 - X / m). This overestimates the actual
+    /// variance, making the bound conservative. The bound is `estimate_count` applied to
+    /// X minus the one-sided normal quantile of confidence times that standard deviation.
+    pub fn min_count_estimate(&self, confidence: f64) -> u64 {
+        assert!(confidence > 0.0 && confidence < 1.0);
+        let low = self.ones as f64 - Self::normal_quantile(confidence) * self.ones_std_dev();
+        Self::count_from_ones(self.bitmap_size, self.k, low.max(0.0)).floor() as u64
+    }
+
+    /// Return a lower and upper bound on the number of distinct items recorded, holding
+    /// with the given two-sided confidence under the binomial model of `min_count_estimate`.
+    /// A bound reaching a saturated fill is `u64::MAX`.
+    pub fn count_confidence_interval(&self, confidence: f64) -> (u64, u64) {
+        assert!(confidence > 0.0 && confidence < 1.0);
+        let margin = Self::normal_quantile((1.0 + confidence) / 2.0) * self.ones_std_dev();
+        let m = self.bitmap_size as f64;
+        let low = (self.ones as f64 - margin).max(0.0);
+        let high = (self.ones as f64 + margin).min(m);
+        (
+            Self::count_from_ones(self.bitmap_size, self.k, low).floor() as u64,
+            Self::count_from_ones(self.bitmap_size, self.k, high).ceil() as u64,
+        )
+    }
+
+    fn ones_std_dev(&self) -> f64 {
+        let ones = self.ones as f64;
+        (ones * (1.0 - ones / self.bitmap_size as f64)).sqrt()
+    }
+
+    fn count_from_ones(bitmap_size: u64, k: u64, ones: f64) -> f64 {
+        let m = bitmap_size as f64;
+        -(m / k as f64) * (1.0 - ones / m).ln()
+    }
+
+    // Abramowitz and Stegun 26.2.23, with an absolute error below 4.5e-4.
+    fn normal_quantile(p: f64) -> f64 {
+        if p < 0.5 {
+            return -Self::normal_quantile(1.0 - p);
+        }
+        let t = (-2.0 * (1.0 - p).ln()).sqrt();
+        t - (2.515517 + 0.802853 * t + 0.010328 * t * t)
+            / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t)
+    }
+
+    /// Return the expected number of bits a new item sets, k * (1 - fill ratio),
+    /// which drops as the filter fills up.
+    pub fn expected_bits_per_insert(&self) -> f64 {
+        self.k as f64 * (1.0 - self.fill_ratio())
+    }
+
+    /// Predict the fill ratio after future_inserts more distinct items are added,
+    /// using 1 - (1 - 1/m)^(k * (n + future_inserts)) with n the number of insertions so far.
+    pub fn predict_fill(&self, future_inserts: u64) -> f64 {
+        let m = self.bitmap_size as f64;
+        let probes = self.k as f64 * (self.insertions as f64 + future_inserts as f64);
+        1.0 - (1.0 - 1.0 / m).powf(probes)
+    }
+
+    /// Return the number of hash functions used for `check` and `set`
+    pub fn number_of_hash_functions(&self) -> u64 {
+        self.k
+    }
+
+    /// Return the seeds used to key the two hash functions
+    pub fn seeds(&self) -> (u64, u64) {
+        self.seeds
+    }
+
+    pub fn xx(&self) -> (XxHash64, XxHash64) {
+        self.xx
+    }
+
+    /// Check if another filter has the same size, number of hash functions and seeds,
+    /// meaning both map every item to the same bits, and bitmaps of the same length,
+    /// so they can be combined bit by bit. Filters built with `new_with_hashers` are never
+    /// compatible, since their hash functions can't be compared.
+    pub fn is_compatible(&self, other: &Bloom) -> bool {
+        self.bitmap_size == other.bitmap_size
+            && self.bitmap.len() == other.bitmap.len()
+            && self.k == other.k
+            && self.seeds == other.seeds
+            && self.hashers.is_none()
+            && other.hashers.is_none()
+    }
+
+    /// Return a hash of the size, number of hash functions and seeds of the filter, equal for
+    /// all compatible filters whatever their contents, and stable across runs and platforms.
+    pub fn config_hash(&self) -> u64 {
+        let mut hasher = Self::xx_new(0);
+        for value in &[self.bitmap_size, self.k, self.seeds.0, self.seeds.1] {
+            hasher.write_u64(*value);
+        }
+        hasher.finish()
+    }
+
+    /// Check that union is compatible with a and b and reports every key recorded
+    /// in either of them, keys_a and keys_b being the keys added to a and b.
+    pub fn verify_union<T: Hash>(a: &Bloom, b: &Bloom, union: &Bloom, keys_a: &[T], keys_b: &[T]) -> bool {
+        union.is_compatible(a)
+            && union.is_compatible(b)
+            && union.assert_contains_all(keys_a).is_ok()
+            && union.assert_contains_all(keys_b).is_ok()
+    }
+
+    /// Add all of the items recorded in another, compatible, filter only if the fill ratio
+    /// of the result stays within max_fill, returning whether the filters were merged.
+    pub fn try_union_bounded(&mut self, other: &Bloom, max_fill: f64) -> Result<bool, BloomError> {
+        if !self.is_compatible(other) {
+            return Err(BloomError::Incompatible);
+        }
+        let ones: u64 = self
+            .bitmap
+            .storage()
+            .iter()
+            .zip(other.bitmap.storage())
+            .map(|(ours, theirs)| u64::from((ours | theirs).count_ones()))
+            .sum();
+        if ones as f64 / self.bitmap_size as f64 > max_fill {
+            return Ok(false);
+        }
+        self.union(other)?;
+        Ok(true)
+    }
+
+    /// Add the bits of another, compatible, filter only in the regions of region_bits bits
+    /// where more than min_region_density of its bits are set, ignoring sparse stray bits.
+    /// Items of `other` with bits in a skipped region are lost, so this trades false
+    /// negatives for `other` against fewer false positives from its noise.
+    /// The insertions of `other` are not counted since it's unknown how many were merged.
+    pub fn union_dense_regions(&mut self, other: &Bloom, min_region_density: f64, region_bits: usize) -> Result<(), BloomError> {
+        assert!(region_bits > 0);
+        if !self.is_compatible(other) {
+            return Err(BloomError::Incompatible);
+        }
+        let len = other.bitmap.len();
+        for start in (0..len).step_by(region_bits) {
+            let end = cmp::min(start + region_bits, len);
+            let ones = (start..end).filter(|&i| other.bitmap[i]).count();
+            if ones as f64 / (end - start) as f64 > min_region_density {
+                for i in (start..end).filter(|&i| other.bitmap[i]) {
+                    self.set_bit(i);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if this filter has a bit set that isn't set in an older, compatible,
+    /// version of it, meaning something was added since.
+    pub fn changed_since(&self, older: &Bloom) -> Result<bool, BloomError> {
+        if !self.is_compatible(older) {
+            return Err(BloomError::Incompatible);
+        }
+        Ok(self
+            .bitmap
+            .storage()
+            .iter()
+            .zip(older.bitmap.storage())
+            .any(|(ours, theirs)| ours & !theirs != 0))
+    }
+
+    /// Return the parameters that differ between this filter and another
+    pub fn param_diff(&self, other: &Bloom) -> ParamDiff {
+        fn diff<T: PartialEq + Copy>(ours: T, theirs: T) -> Option<(T, T)> {
+            if ours == theirs {
+                None
+            } else {
+                Some((ours, theirs))
+            }
+        }
+        ParamDiff {
+            bitmap_size: diff(self.bitmap_size, other.bitmap_size),
+            bitmap_len: diff(self.bitmap.len(), other.bitmap.len()),
+            k: diff(self.k, other.k),
+            seeds: diff(self.seeds, other.seeds),
+        }
+    }
+
+    /// Add all of the items recorded in another, compatible, filter.
+    /// The insertions of both filters are summed, including items recorded in both,
+    /// and their content roots are combined.
+    pub fn union(&mut self, other: &Bloom) -> Result<(), BloomError> {
+        if !self.is_compatible(other) {
+            return Err(BloomError::Incompatible);
+        }
+        self.bitmap.or(&other.bitmap);
+        self.insertions += other.insertions;
+        self.ones = Self::popcount(&self.bitmap);
+        self.root ^= other.root;
+        Ok(())
+    }
+
+    /// Render the filter as a header line with its parameters followed by
+    /// rows of 64 `0`/`1` characters, for debugging small filters.
+    pub fn to_ascii(&self) -> String {
+        self.to_ascii_with_width(64)
+    }
+
+    /// Render the filter as a header line with its parameters followed by
+    /// rows of `row_width` `0`/`1` characters.
+    /// Filters larger than `ASCII_MAX_BITS` only get the header and a summary line.
+    pub fn to_ascii_with_width(&self, row_width: usize) -> String {
+        assert!(row_width > 0);
+        let mut out = format!(
+            "bits: {}, hash functions: {}, seeds: {:?}\n",
+            self.bitmap_size, self.k, self.seeds
+        );
+        if self.bitmap_size > ASCII_MAX_BITS {
+            out.push_str(&format!("{} bits set, too large to render\n", self.count_ones()));
+            return out;
+        }
+        for (i, bit) in self.bitmap.iter().enumerate() {
+            out.push(if bit { '1' } else { '0' });
+            if (i + 1) % row_width == 0 || i + 1 == self.bitmap.len() {
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Time the insertion of `n` keys, generated from `rng_seed`, into a filter
+    /// sized for them with a 1e-3 false positive rate.
+    #[cfg(feature = "bench")]
+    pub fn bench_insert(n: usize, rng_seed: u64) -> Duration {
+        let keys = Self::bench_keys(n, rng_seed);
+        let mut bloom = Bloom::new_with_rate(n, 1e-3);
+        let start = Instant::now();
+        for key in &keys {
+            bloom.add(key);
+        }
+        start.elapsed()
+    }
+
+    /// Time `n` lookups, half of them for present keys, in a filter holding `n / 2`
+    /// keys generated from `rng_seed`.
+    #[cfg(feature = "bench")]
+    pub fn bench_check(n: usize, rng_seed: u64) -> Duration {
+        let keys = Self::bench_keys(n, rng_seed);
+        let mut bloom = Bloom::new_with_rate(n, 1e-3);
+        for key in keys.iter().step_by(2) {
+            bloom.add(key);
+        }
+        let start = Instant::now();
+        for key in &keys {
+            bloom.check(key);
+        }
+        start.elapsed()
+    }
+
+    #[cfg(feature = "bench")]
+    fn bench_keys(n: usize, rng_seed: u64) -> Vec<u64> {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        (0..n).map(|_| rng.gen()).collect()
+    }
+
+    /// Return the fraction of the bits set in other that are also set in this filter, which
+    /// must be compatible. Close to 1.0, merging other in would add little; an empty other
+    /// adds nothing and gives 1.0.
+    pub fn merge_benefit(&self, other: &Bloom) -> Result<f64, BloomError> {
+        if !self.is_compatible(other) {
+            return Err(BloomError::Incompatible);
+        }
+        if other.ones == 0 {
+            return Ok(1.0);
+        }
+        let mut common = self.bitmap.clone();
+        common.and(&other.bitmap);
+        Ok(Self::popcount(&common) as f64 / other.ones as f64)
+    }
+
+    /// Return true if the bits set in both filters are no more than 3 standard deviations
+    /// above what independent fills would share by chance, hinting that no item was recorded
+    /// in both.
+    pub fn likely_disjoint(&self, other: &Bloom) -> Result<bool, BloomError> {
+        if !self.is_compatible(other) {
+            return Err(BloomError::Incompatible);
+        }
+        let mut common = self.bitmap.clone();
+        common.and(&other.bitmap);
+        let m = self.bitmap_size as f64;
+        let chance = (self.ones as f64 / m) * (other.ones as f64 / m);
+        let expected = m * chance;
+        let std_dev = (m * chance * (1.0 - chance)).sqrt();
+        Ok(Self::popcount(&common) as f64 <= expected + 3.0 * std_dev)
+    }
+
+    /// Return a MinHash signature of the set bit positions: the smallest hash of any set
+    /// offset under each of num_hashes seeded XXHash64 functions, or `u64::MAX` for an empty
+    /// filter. The fraction of equal entries in two signatures estimates the Jaccard index
+    /// of their set bits, which grows with the Jaccard index of the recorded items.
+    pub fn minhash_signature(&self, num_hashes: usize) -> Vec<u64> {
+        let mut signature = vec![u64::MAX; num_hashes];
+        for (offset, _) in self.bitmap.iter().enumerate().filter(|&(_, bit)| bit) {
+            for (seed, min) in signature.iter_mut().enumerate() {
+                let mut hasher = Self::xx_new(seed as u64);
+                hasher.write_u64(offset as u64);
+                *min = cmp::min(*min, hasher.finish());
+            }
+        }
+        signature
+    }
+
+    /// Return the number of bits set in all of the filters, which must be compatible.
+    /// This approximates the bits of the items recorded in every filter.
+    pub fn common_bits(filters: &[&Bloom]) -> Result<u64, BloomError> {
+        let (first, rest) = match filters.split_first() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        let mut common = first.bitmap.clone();
+        for other in rest {
+            if !first.is_compatible(other) {
+                return Err(BloomError::Incompatible);
+            }
+            common.and(&other.bitmap);
+        }
+        Ok(Self::popcount(&common))
+    }
+
+    /// Return the union of this filter and another, compatible, one with every bit
+    /// then flipped with probability flip_prob, in [0.0, 1.0].
+    /// This is a randomized response: any single bit is plausibly deniable, which hides
+    /// whether a given item was recorded. The price is accuracy: cleared bits introduce
+    /// false negatives and set bits raise the false positive rate, both growing with flip_prob.
+    pub fn noisy_union<R: Rng>(&self, other: &Bloom, flip_prob: f64, rng: &mut R) -> Result<Bloom, BloomError> {
+        assert!((0.0..=1.0).contains(&flip_prob));
+        let mut union = Bloom::from_existing_struct(self);
+        union.union(other)?;
+        for i in 0..union.bitmap.len() {
+            if rng.gen_bool(flip_prob) {
+                let bit = union.bitmap[i];
+                union.bitmap.set(i, !bit);
+            }
+        }
+        union.ones = Self::popcount(&union.bitmap);
+        Ok(union)
+    }
+
+    /// Return how many more bits the filter has than the theoretical minimum needed to reach
+    /// the false positive rate it actually achieves with its integer k once items_count items
+    /// are stored, as a fraction of that minimum.
+    /// The value is never meaningfully negative; the further k is from optimal, the larger it gets.
+    pub fn over_allocation_ratio(&self, items_count: usize) -> f64 {
+        assert!(items_count > 0);
+        let realized_fp = Self::false_positive_rate(self.bitmap_size, self.k, items_count as f64);
+        let log2 = f64::consts::LN_2;
+        let min_bits = -(items_count as f64) * f64::ln(realized_fp) / (log2 * log2);
+        self.bitmap_size as f64 / min_bits - 1.0
+    }
+
+    /// Estimate the false positive rate after items_count distinct items are recorded,
+    /// accounting for double hashing sometimes probing the same bit twice.
+    /// The number of distinct bits probed is measured for a fixed sample of keys: its mean
+    /// gives the fill ratio p, and the rate is the mean of p^ki over the sampled keys,
+    /// instead of the textbook (1 - e^(-k * n / m))^k.
+    pub fn corrected_fp_rate(&self, items_count: usize) -> f64 {
+        const SAMPLE: usize = 1_000;
+        let mut rng = StdRng::seed_from_u64(0);
+        let effective: Vec<f64> = (0..SAMPLE).map(|_| self.effective_k(&rng.gen::<u64>()) as f64).collect();
+        let mean_k = effective.iter().sum::<f64>() / SAMPLE as f64;
+        let fill = 1.0 - (-mean_k * items_count as f64 / self.bitmap_size as f64).exp();
+        effective.iter().map(|&k| fill.powf(k)).sum::<f64>() / SAMPLE as f64
+    }
+
+    fn false_positive_rate(bitmap_size: u64, k: u64, items_count: f64) -> f64 {
+        let k = k as f64;
+        (1.0 - (-k * items_count / bitmap_size as f64).exp()).powf(k)
+    }
+
+    fn optimal_k_num(bitmap_size: u64, items_count: usize) -> u64 {
+        let m = bitmap_size as f64;
+        let n = items_count as f64;
+        let k = (m / n * f64::ln(2.0f64)).ceil() as u64;
+        cmp::max(k, 1)
+    }
+
+    fn hash1<T: Hash>(&self, t: &T) -> u64 {
+        let mut hasher = self.xx().0;
+        t.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn hash2<T: Hash>(&self, t: &T) -> u64 {
+        let mut hasher = self.xx().1;
+        t.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn hashes<T: Hash>(&self, t: &T) -> (u64, u64) {
+        if let Some((ref h1, ref h2)) = self.hashers {
+            let mut bytes = ByteCollector(Vec::new());
+            t.hash(&mut bytes);
+            return (h1(&bytes.0), h2(&bytes.0));
+        }
+        (self.hash1(t), self.hash2(t))
+    }
+
+    fn double_hash(&self, hashes: (u64, u64), i_k: u64) -> u64 {
+        Self::double_hash_with(hashes, i_k, self.bitmap_size)
+    }
+
+    fn double_hash_with(hashes: (u64, u64), i_k: u64, bitmap_size: u64) -> u64 {
+        hashes.0.wrapping_add(i_k.wrapping_mul(hashes.1)) % bitmap_size
+    }
+
+    /// Clear all of the bits in the filter, removing all keys from the set
+    pub fn clear(&mut self) {
+        self.bitmap.clear();
+        self.insertions = 0;
+        self.ones = 0;
+        self.root = 0;
+        self.clear_recent_inserts();
+    }
+
+    /// Clear the filter and replace its bitmap with a new one of bitmap_size bytes,
+    /// recomputing the number of hash functions for items_count items.
+    /// The old bitmap is deallocated, the seeds are kept.
+    pub fn clear_and_resize(&mut self, bitmap_size: usize, items_count: usize) {
+        assert!(bitmap_size > 0 && items_count > 0);
+        let bitmap_size = (bitmap_size as u64) * 8u64;
+        self.bitmap = BitVec::from_elem(bitmap_size as usize, false);
+        self.bitmap_size = bitmap_size;
+        self.k = Self::optimal_k_num(bitmap_size, items_count);
+        self.insertions = 0;
+        self.ones = 0;
+        self.root = 0;
+        self.clear_recent_inserts();
+    }
+
+    /// Freeze the filter once it is built: drop the recent inserts buffer and spare
+    /// capacity, and only allow queries from now on.
+    pub fn seal(mut self) -> SealedBloom {
+        self.recent = None;
+        self.bitmap.shrink_to_fit();
+        SealedBloom { bloom: self }
+    }
+
+    fn record_insertion(&mut self, hashes: (u64, u64)) {
+        self.insertions += 1;
+        self.root ^= hashes.0 ^ hashes.1.rotate_left(32);
+        if let Some((capacity, ref mut recent)) = self.recent {
+            if recent.len() == 2 * capacity {
+                recent.drain(..capacity);
+            }
+            recent.push(hashes);
+        }
+    }
+
+    fn clear_recent_inserts(&mut self) {
+        if let Some((_, ref mut recent)) = self.recent {
+            recent.clear();
+        }
+    }
+
+    /// Start keeping the hash pairs of the last `capacity` inserted items for debugging, or
+    /// stop if capacity is 0. Tracking is off by default and costs nothing until enabled.
+    pub fn track_recent_inserts(&mut self, capacity: usize) {
+        self.recent = if capacity == 0 {
+            None
+        } else {
+            Some((capacity, Vec::with_capacity(2 * capacity)))
+        };
+    }
+
+    /// Return the hash pairs of the most recently inserted items, oldest first, or an empty
+    /// slice unless `track_recent_inserts` was called.
+    pub fn recent_inserts(&self) -> &[(u64, u64)] {
+        match self.recent {
+            Some((capacity, ref recent)) => &recent[recent.len().saturating_sub(capacity)..],
+            None => &[],
+        }
+    }
+
+    fn set_bit(&mut self, offset: usize) {
+        if !self.bitmap[offset] {
+            self.bitmap.set(offset, true);
+            self.ones += 1;
+        }
+    }
+
+    fn set_random_bits<R: Rng>(&mut self, ones: u64, rng: &mut R) {
+        assert!(ones <= self.bitmap.len() as u64);
+        while self.ones < ones {
+            let offset = rng.gen_range(0, self.bitmap.len());
+            self.set_bit(offset);
+        }
+    }
+
+    fn popcount(bitmap: &BitVec) -> u64 {
+        bitmap.storage().iter().map(|word| u64::from(word.count_ones())).sum()
+    }
+
+    fn xx_new(seed: u64) -> XxHash64 {
+        XxHash64::with_seed(seed)
+    }
+}
+
+/// Several bloom filters sharing their seeds and fed the same items, each item being
+/// hashed once for all of them
+pub struct MultiBloom {
+    filters: Vec<Bloom>,
+}
+
+impl MultiBloom {
+    /// Create one empty filter per `(items_count, fp_p)` configuration, all using seeds
+    pub fn new(configs: &[(usize, f64)], seeds: (u64, u64)) -> Self {
+        assert!(!configs.is_empty());
+        let filters = configs
+            .iter()
+            .map(|&(items_count, fp_p)| Bloom::new_for_union(items_count, fp_p, seeds))
+            .collect();
+        Self { filters }
+    }
+
+    /// Record the presence of an item in every filter.
+    pub fn add<T: Hash>(&mut self, item: &T) {
+        let hashes = self.filters[0].hashes(item);
+        for bloom in &mut self.filters {
+            bloom.add_hashes(hashes);
+        }
+    }
+
+    /// Check if an item is present in each of the filters.
+    pub fn check<T: Hash>(&self, item: &T) -> Vec<bool> {
+        let hashes = self.filters[0].hashes(item);
+        self.filters.iter().map(|bloom| bloom.check_hashes(hashes)).collect()
+    }
+
+    /// Return the filters, in the order of their configurations
+    pub fn filters(&self) -> &[Bloom] {
+        &self.filters
+    }
+
+    /// Return the filters, in the order of their configurations
+    pub fn into_filters(self) -> Vec<Bloom> {
+        self.filters
+    }
+}
+
+/// Bloom filter structure also counting insertions per region of its bitmap, an item
+/// belonging to the region of the first bit it probes, to spot the hottest keys
+pub struct HybridBloom {
+    bloom: Bloom,
+    counters: Vec<u64>,
+}
+
+impl HybridBloom {
+    /// Create a new bloom filter structure, see `Bloom::new`, with `regions` counters.
+    pub fn new(bitmap_size: usize, items_count: usize, regions: usize) -> Self {
+        let bloom = Bloom::new(bitmap_size, items_count);
+        assert!(regions > 0 && regions as u64 <= bloom.bitmap_size);
+        Self {
+            bloom,
+            counters: vec![0; regions],
+        }
+    }
+
+    /// Record the presence of an item and count it in its region.
+    pub fn add<T: Hash>(&mut self, item: &T) {
+        let hashes = self.bloom.hashes(item);
+        let region = self.region_of_hashes(hashes);
+        self.counters[region] += 1;
+        self.bloom.add_hashes(hashes);
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T: Hash>(&self, item: &T) -> bool {
+        self.bloom.check(item)
+    }
+
+    /// Return the region an item is counted in
+    pub fn region_of<T: Hash>(&self, item: &T) -> usize {
+        self.region_of_hashes(self.bloom.hashes(item))
+    }
+
+    /// Return the n regions with the most insertions and their counts, most inserted first
+    pub fn hottest_regions(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut regions: Vec<(usize, u64)> = self.counters.iter().cloned().enumerate().collect();
+        regions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        regions.truncate(n);
+        regions
+    }
+
+    /// Return the underlying filter
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    fn region_of_hashes(&self, hashes: (u64, u64)) -> usize {
+        let offset = self.bloom.bit_offset(hashes, 0) as u64;
+        (offset * self.counters.len() as u64 / self.bloom.bitmap_size) as usize
+    }
+}
+
+/// Read-only bloom filter structure, see `Bloom::seal`
+pub struct SealedBloom {
+    bloom: Bloom,
+}
+
+impl SealedBloom {
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T: Hash>(&self, item: &T) -> bool {
+        self.bloom.check(item)
+    }
+
+    /// Return the bitmap
+    pub fn bitmap(&self) -> &BitVec {
+        &self.bloom.bitmap
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bloom.bitmap_size
+    }
+
+    /// Return the number of hash functions used for `check`
+    pub fn number_of_hash_functions(&self) -> u64 {
+        self.bloom.k
+    }
+
+    /// Return the seeds used to key the two hash functions
+    pub fn seeds(&self) -> (u64, u64) {
+        self.bloom.seeds
+    }
+
+    /// Return the number of items recorded before the filter was sealed
+    pub fn insertions(&self) -> u64 {
+        self.bloom.insertions
+    }
+
+    /// Return the number of bits set
+    pub fn count_ones(&self) -> u64 {
+        self.bloom.ones
+    }
+
+    /// Return the false positive rate implied by the fill ratio, see `Bloom::current_fp_rate`
+    pub fn current_fp_rate(&self) -> f64 {
+        self.bloom.current_fp_rate()
+    }
+}
+
+/// Bloom filter structure using a borrowed buffer as its bitmap, see `Bloom::new_in_buffer`
+pub struct BufferBloom<'a> {
+    bitmap: &'a mut [u8],
+    bitmap_size: u64,
+    k: u64,
+    xx: (XxHash64, XxHash64),
+}
+
+impl<'a> BufferBloom<'a> {
+    /// Record the presence of an item.
+    pub fn add<T: Hash>(&mut self, item: &T) {
+        for offset in self.offsets(item) {
+            self.bitmap[offset / 8] |= 0x80 >> (offset % 8);
+        }
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T: Hash>(&self, item: &T) -> bool {
+        self.offsets(item)
+            .iter()
+            .all(|&offset| self.bitmap[offset / 8] & (0x80 >> (offset % 8)) != 0)
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bitmap_size
+    }
+
+    /// Return the number of hash functions used for `check` and `set`
+    pub fn number_of_hash_functions(&self) -> u64 {
+        self.k
+    }
+
+    fn offsets<T: Hash>(&self, item: &T) -> Vec<usize> {
+        let mut hashers = self.xx;
+        item.hash(&mut hashers.0);
+        item.hash(&mut hashers.1);
+        let hashes = (hashers.0.finish(), hashers.1.finish());
+        (0..self.k)
+            .map(|i_k| Bloom::double_hash_with(hashes, i_k, self.bitmap_size) as usize)
+            .collect()
+    }
+}
+
+/// Structures answering approximate membership queries, to write code generic over them
+pub trait Membership<T> {
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    fn check(&self, item: &T) -> bool;
+}
+
+impl<T: Hash> Membership<T> for Bloom {
+    fn check(&self, item: &T) -> bool {
+        Bloom::check(self, item)
+    }
+}
+
+impl<T: Hash> Membership<T> for SealedBloom {
+    fn check(&self, item: &T) -> bool {
+        SealedBloom::check(self, item)
+    }
+}
+
+impl<T: Hash> Membership<T> for HybridBloom {
+    fn check(&self, item: &T) -> bool {
+        HybridBloom::check(self, item)
+    }
+}
+
+impl<'a, T: Hash> Membership<T> for BufferBloom<'a> {
+    fn check(&self, item: &T) -> bool {
+        BufferBloom::check(self, item)
+    }
+}
+
+#[test]
+fn bloom_test_add() {
+    let mut bloom = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    assert!(!bloom.check(&key));
+    bloom.add(&key);
+    assert!(bloom.check(&key));
+}
+
+#[test]
 fn bloom_test_check_and_add() {
     let mut bloom = Bloom::new(100, 10);
-    let key: u64 = rand::random();
-    assert_eq!(bloom.check_and_add(&key), false);
-    assert_eq!(bloom.check_and_add(&key), true);
+    let key: u64 = rand::random();
+    assert!(!bloom.check_and_add(&key));
+    assert!(bloom.check_and_add(&key));
+}
+
+#[test]
+fn bloom_test_clear() {
+    let mut bloom = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    bloom.add(&key);
+    assert!(bloom.check(&key));
+    bloom.clear();
+    assert!(!bloom.check(&key));
+}
+
+#[test]
+fn bloom_test_load() {
+    let mut original = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    original.add(&key);
+    assert!(original.check(&key));
+
+    let cloned = Bloom::from_existing(
+        &original.bitmap().to_bytes(),
+        original.number_of_bits(),
+        original.number_of_hash_functions(),
+        original.seeds(),
+    );
+    assert!(cloned.check(&key));
+}
+
+#[test]
+fn bloom_test_load_struct() {
+    let mut original = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    original.add(&key);
+    assert!(original.check(&key));
+
+    let cloned = Bloom::from_existing_struct(&original);
+    assert!(cloned.check(&key));
+}
+
+#[test]
+fn bloom_test_new_for_union() {
+    let seeds = (rand::random(), rand::random());
+    let mut first = Bloom::new_for_union(2_000, 0.01, seeds);
+    let mut second = Bloom::new_for_union(2_000, 0.01, seeds);
+    let keys: Vec<u64> = (0..2_000).map(|_| rand::random()).collect();
+    for key in &keys[..1_000] {
+        first.add(key);
+    }
+    for key in &keys[1_000..] {
+        second.add(key);
+    }
+    first.union(&second).unwrap();
+    assert!(keys.iter().all(|key| first.check(key)));
+
+    let false_positives = (0..10_000)
+        .filter(|_| first.check(&rand::random::<u64>()))
+        .count();
+    assert!(false_positives < 200);
+
+    let other = Bloom::new_for_union(2_000, 0.01, (seeds.0.wrapping_add(1), seeds.1));
+    assert_eq!(first.union(&other), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_absent_iter() {
+    let mut bloom = Bloom::new(1_000, 100);
+    let keys: Vec<u64> = (0..200).map(|_| rand::random()).collect();
+    for key in keys.iter().step_by(2) {
+        bloom.add(key);
+    }
+    let lazy: Vec<&u64> = bloom.absent_iter(keys.iter()).collect();
+    assert_eq!(lazy, bloom.filter_absent(&keys));
+    assert!(lazy.iter().all(|key| !bloom.check(key)));
+    assert!(lazy.len() <= 100);
+}
+
+#[test]
+fn bloom_test_to_ascii() {
+    let bloom = Bloom::from_existing(&[0x80, 0, 0, 0, 0, 0, 0, 0x01], 64, 2, (1, 2));
+    let expected = "bits: 64, hash functions: 2, seeds: (1, 2)\n\
+                    1000000000000000\n\
+                    0000000000000000\n\
+                    0000000000000000\n\
+                    0000000000000001\n";
+    assert_eq!(bloom.to_ascii_with_width(16), expected);
+    assert_eq!(bloom.to_ascii().lines().count(), 2);
+
+    let large = Bloom::new(1_000, 10);
+    assert_eq!(large.to_ascii().lines().count(), 2);
+    assert!(large.to_ascii().ends_with("too large to render\n"));
+}
+
+#[test]
+fn bloom_test_new_capped_k() {
+    let uncapped = Bloom::new(1_000, 100);
+    assert!(uncapped.number_of_hash_functions() > 2);
+    let mut bloom = Bloom::new_capped_k(1_000, 100, 2);
+    assert_eq!(bloom.number_of_hash_functions(), 2);
+    let key: u64 = rand::random();
+    bloom.add(&key);
+    assert!(bloom.check(&key));
+    assert!(bloom.bitmap().iter().filter(|bit| *bit).count() <= 2);
+}
+
+#[cfg(feature = "bench")]
+#[test]
+fn bloom_test_bench_helpers() {
+    assert!(Bloom::bench_insert(1_000, 42) > Duration::new(0, 0));
+    assert!(Bloom::bench_check(1_000, 42) > Duration::new(0, 0));
+}
+
+#[test]
+fn bloom_test_clear_and_resize() {
+    let mut bloom = Bloom::new(10_000, 1_000);
+    let key: u64 = rand::random();
+    bloom.add(&key);
+    bloom.clear_and_resize(100, 10);
+    assert_eq!(bloom.number_of_bits(), 800);
+    assert_eq!(bloom.bitmap().len(), 800);
+    assert_eq!(bloom.number_of_hash_functions(), Bloom::new(100, 10).number_of_hash_functions());
+    assert!(bloom.bitmap().none());
+    bloom.add(&key);
+    assert!(bloom.check(&key));
+}
+
+#[test]
+fn bloom_test_assert_contains_all() {
+    let mut bloom = Bloom::new(1_000, 10);
+    let keys: Vec<u64> = (0..10).map(|_| rand::random()).collect();
+    for key in &keys {
+        bloom.add(key);
+    }
+    assert_eq!(bloom.assert_contains_all(&keys), Ok(()));
+
+    let offset = bloom.bit_offset(bloom.hashes(&keys[3]), 0);
+    bloom.bitmap.set(offset, false);
+    bloom.ones = Bloom::popcount(&bloom.bitmap);
+    let missing = bloom.assert_contains_all(&keys).unwrap_err();
+    assert!(missing.contains(&3));
+}
+
+#[test]
+fn bloom_test_predict_fill() {
+    let mut bloom = Bloom::new(1_000, 1_000);
+    for _ in 0..200 {
+        bloom.add(&rand::random::<u64>());
+    }
+    assert_eq!(bloom.insertions(), 200);
+    let predicted = bloom.predict_fill(600);
+    for _ in 0..600 {
+        bloom.add(&rand::random::<u64>());
+    }
+    assert!((predicted - bloom.fill_ratio()).abs() < 0.03);
+    assert!((bloom.predict_fill(0) - bloom.fill_ratio()).abs() < 0.03);
+    assert!(bloom.predict_fill(u64::MAX / 2) > 0.999);
+    assert!(bloom.predict_fill(u64::MAX) > 0.999);
+}
+
+#[test]
+fn bloom_test_new_optimized_for_reads() {
+    let classic = Bloom::new_with_rate(10_000, 0.01);
+    let all_positive = Bloom::new_optimized_for_reads(10_000, 0.01, 0.0);
+    let mostly_negative = Bloom::new_optimized_for_reads(10_000, 0.01, 0.95);
+    assert_eq!(all_positive.number_of_bits(), classic.number_of_bits());
+    assert_eq!(mostly_negative.number_of_bits(), classic.number_of_bits());
+    assert!(all_positive.number_of_hash_functions() <= classic.number_of_hash_functions());
+    assert!(mostly_negative.number_of_hash_functions() < classic.number_of_hash_functions());
+}
+
+#[test]
+fn bloom_test_split() {
+    let mut original = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    original.add(&key);
+
+    let mut meta = Vec::new();
+    let mut bitmap = Vec::new();
+    original.to_split(&mut meta, &mut bitmap).unwrap();
+    assert_eq!(meta.len(), 32);
+    assert_eq!(bitmap.len(), 100);
+
+    let loaded = Bloom::from_split(&mut meta.as_slice(), &mut bitmap.as_slice()).unwrap();
+    assert!(loaded.check(&key));
+    assert!(loaded.is_compatible(&original));
+
+    let truncated = Bloom::from_split(&mut meta.as_slice(), &mut &bitmap[..50]);
+    assert_eq!(truncated.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn bloom_test_unique_offsets() {
+    let bloom = Bloom::from_existing(&[0], 8, 16, (1, 2));
+    let key: u64 = rand::random();
+    let probes = bloom.probe_offsets(&key);
+    let unique = bloom.unique_offsets(&key);
+    assert_eq!(probes.len(), 16);
+    assert!(unique.len() < probes.len());
+    assert!(unique.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(probes.iter().all(|offset| unique.contains(offset)));
+}
+
+#[test]
+fn bloom_test_noisy_union() {
+    let seeds = (rand::random(), rand::random());
+    let mut first = Bloom::new_for_union(100, 0.01, seeds);
+    let mut second = Bloom::new_for_union(100, 0.01, seeds);
+    for _ in 0..50 {
+        first.add(&rand::random::<u64>());
+        second.add(&rand::random::<u64>());
+    }
+    let mut union = Bloom::from_existing_struct(&first);
+    union.union(&second).unwrap();
+
+    let mut rng = rand::thread_rng();
+    let exact = first.noisy_union(&second, 0.0, &mut rng).unwrap();
+    assert_eq!(exact.bitmap(), union.bitmap());
+    let noisy = first.noisy_union(&second, 0.2, &mut rng).unwrap();
+    assert_ne!(noisy.bitmap(), union.bitmap());
+
+    let other = Bloom::new_for_union(100, 0.01, (seeds.0.wrapping_add(1), seeds.1));
+    assert!(first.noisy_union(&other, 0.0, &mut rng).is_err());
+
+    let unaligned = Bloom::random(100, 3, seeds, 0.2, 1);
+    let unaligned_other = Bloom::random(100, 3, seeds, 0.2, 2);
+    let exact = unaligned.noisy_union(&unaligned_other, 0.0, &mut rng).unwrap();
+    assert_eq!(exact.bitmap().len(), 100);
+    let mut expected = unaligned.bitmap();
+    expected.or(&unaligned_other.bitmap());
+    assert_eq!(exact.bitmap(), expected);
+}
+
+#[test]
+fn bloom_test_common_bits() {
+    let seeds = (rand::random(), rand::random());
+    let common_keys: Vec<u64> = (0..20).map(|_| rand::random()).collect();
+    let mut only_common = Bloom::new_for_union(1_000, 0.01, seeds);
+    for key in &common_keys {
+        only_common.add(key);
+    }
+    let filters: Vec<Bloom> = (0..3)
+        .map(|_| {
+            let mut bloom = Bloom::from_existing_struct(&only_common);
+            for _ in 0..100 {
+                bloom.add(&rand::random::<u64>());
+            }
+            bloom
+        })
+        .collect();
+    let refs: Vec<&Bloom> = filters.iter().collect();
+    let common = Bloom::common_bits(&refs).unwrap();
+    assert!(common >= only_common.count_ones());
+    assert!(common < only_common.count_ones() + 20);
+
+    assert_eq!(Bloom::common_bits(&[]), Ok(0));
+    let other = Bloom::new(100, 10);
+    assert_eq!(Bloom::common_bits(&[&filters[0], &other]), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_combine_padded_bitmap() {
+    let bloom = Bloom::new(100, 10);
+    let mut padded = Bloom::from_existing_struct(&bloom);
+    padded.bitmap.grow(4, false);
+    assert!(!bloom.is_compatible(&padded));
+    let diff = bloom.param_diff(&padded);
+    assert_eq!(diff.bitmap_len, Some((800, 804)));
+    assert_eq!(diff.to_string(), "bitmap_len: 800 != 804");
+    assert_eq!(Bloom::distinct_estimate_across(&[&bloom, &padded]), Err(BloomError::Incompatible));
+    assert_eq!(Bloom::common_bits(&[&bloom, &padded]), Err(BloomError::Incompatible));
+    assert_eq!(bloom.merge_benefit(&padded), Err(BloomError::Incompatible));
+    assert_eq!(bloom.likely_disjoint(&padded), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_sample_set_bit() {
+    let mut rng = rand::thread_rng();
+    let empty = Bloom::new(8, 1);
+    assert_eq!(empty.sample_set_bit(&mut rng), None);
+
+    let bitmap = [0x81, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0x01];
+    let bloom = Bloom::from_existing(&bitmap, 96, 1, (1, 2));
+    assert_eq!(bloom.count_ones(), 4);
+    let set_bits = [0, 7, 35, 95];
+    let mut counts = [0; 4];
+    for _ in 0..8_000 {
+        let offset = bloom.sample_set_bit(&mut rng).unwrap();
+        let i = set_bits.iter().position(|&bit| bit == offset).unwrap();
+        counts[i] += 1;
+    }
+    assert!(counts.iter().all(|&count| count > 1_700 && count < 2_300));
+
+    let dense = Bloom::from_existing(&[0xF0; 12], 96, 1, (1, 2));
+    let mut counts = [0; 96];
+    for _ in 0..9_600 {
+        counts[dense.sample_set_bit(&mut rng).unwrap()] += 1;
+    }
+    for (offset, &count) in counts.iter().enumerate() {
+        if dense.bitmap.get(offset) == Some(true) {
+            assert!(count > 100 && count < 300);
+        } else {
+            assert_eq!(count, 0);
+        }
+    }
+}
+
+#[test]
+fn bloom_test_synthetic_like() {
+    let mut original = Bloom::new(1_000, 500);
+    for _ in 0..300 {
+        original.add(&rand::random::<u64>());
+    }
+    let synthetic = Bloom::synthetic_like(&original, 42);
+    assert!(synthetic.is_compatible(&original));
+    assert_eq!(synthetic.count_ones(), original.count_ones());
+    assert_eq!(synthetic.count_ones(), synthetic.bitmap().iter().filter(|bit| *bit).count() as u64);
+    assert_ne!(synthetic.bitmap(), original.bitmap());
+    assert_eq!(Bloom::synthetic_like(&original, 42).bitmap(), synthetic.bitmap());
+}
+
+#[test]
+fn bloom_test_lsb_first() {
+    let original = Bloom::from_existing(&[0x80, 0x03], 16, 1, (1, 2));
+    assert!(original.bitmap()[0]);
+    let lsb_first = original.to_bytes_lsb_first();
+    assert_eq!(lsb_first, vec![0x01, 0xc0]);
+    assert_ne!(lsb_first, original.bitmap().to_bytes());
+
+    let loaded = Bloom::from_bytes_lsb_first(&lsb_first, 16, 1, (1, 2));
+    assert_eq!(loaded.bitmap(), original.bitmap());
+}
+
+#[test]
+fn bloom_test_effective_k() {
+    let bloom = Bloom::from_existing(&[0; 8], 64, 10, (1, 2));
+    let effective: Vec<u64> = (0..100u64).map(|key| bloom.effective_k(&key)).collect();
+    assert!(effective.iter().all(|k| (1..=10).contains(k)));
+    assert!(effective.iter().any(|&k| k < 10));
+}
+
+#[test]
+fn bloom_test_build_from_sorted_file() {
+    let mut keys: Vec<u64> = (0..1_000).map(|_| rand::random()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    let bytes: Vec<u8> = keys.iter().flat_map(|key| key.to_le_bytes().to_vec()).collect();
+    let path = std::env::temp_dir().join(format!("xx-bloomfilter-{}.keys", rand::random::<u64>()));
+    std::fs::write(&path, &bytes).unwrap();
+
+    let bloom = Bloom::build_from_sorted_file(&path, 0.01);
+    std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+    let truncated = Bloom::build_from_sorted_file(&path, 0.01);
+    std::fs::remove_file(&path).unwrap();
+
+    let bloom = bloom.unwrap();
+    assert_eq!(bloom.insertions(), keys.len() as u64);
+    assert!(keys.iter().step_by(10).all(|key| bloom.check(key)));
+    assert_eq!(truncated.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn bloom_test_over_allocation_ratio() {
+    let bloom = Bloom::new_with_rate(10_000, 0.01);
+    let ratio = bloom.over_allocation_ratio(10_000);
+    assert!(ratio > 0.0 && ratio < 0.01);
+    let capped = Bloom::new_capped_k(bloom.number_of_bits() as usize / 8, 10_000, 2);
+    assert_eq!(capped.number_of_bits(), bloom.number_of_bits());
+    assert!(capped.over_allocation_ratio(10_000) > 0.2);
+    assert!(bloom.over_allocation_ratio(5_000) > 0.0);
+}
+
+#[test]
+fn bloom_test_add_range() {
+    let mut bloom = Bloom::new_with_rate(1_000, 0.01);
+    bloom.add_range(0..1_000);
+    assert_eq!(bloom.insertions(), 1_000);
+    assert!((0..1_000u64).step_by(7).all(|key| bloom.check(&key)));
+    let false_positives = (1_000..11_000u64).filter(|key| bloom.check(key)).count();
+    assert!(false_positives < 300);
+}
+
+#[test]
+fn bloom_test_param_diff() {
+    let first = Bloom::new_for_union(100, 0.01, (1, 2));
+    let second = Bloom::new_for_union(100, 0.01, (3, 2));
+    let diff = first.param_diff(&second);
+    assert_eq!(diff.bitmap_size, None);
+    assert_eq!(diff.k, None);
+    assert_eq!(diff.seeds, Some(((1, 2), (3, 2))));
+    assert_eq!(diff.to_string(), "seeds: (1, 2) != (3, 2)");
+
+    let same = first.param_diff(&Bloom::from_existing_struct(&first));
+    assert!(same.is_empty());
+    assert_eq!(same.to_string(), "no differences");
+}
+
+#[test]
+fn bloom_test_new_named() {
+    let mut first = Bloom::new_named("users", 1_000, 0.01);
+    let second = Bloom::new_named("users", 1_000, 0.01);
+    assert!(first.is_compatible(&second));
+    let key: u64 = rand::random();
+    assert_eq!(first.probe_offsets(&key), second.probe_offsets(&key));
+    first.add(&key);
+    let mut union = Bloom::from_existing_struct(&second);
+    union.union(&first).unwrap();
+    assert!(union.check(&key));
+
+    let other = Bloom::new_named("groups", 1_000, 0.01);
+    assert!(!first.is_compatible(&other));
+}
+
+#[test]
+fn bloom_test_estimate_count() {
+    let mut bloom = Bloom::new_with_rate(10_000, 0.01);
+    assert_eq!(bloom.estimate_count(), 0);
+    for _ in 0..5_000 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let estimate = bloom.estimate_count();
+    assert!(estimate > 4_800 && estimate < 5_200);
+}
+
+#[test]
+fn bloom_test_distinct_estimate_across() {
+    let seeds = (rand::random(), rand::random());
+    let keys: Vec<u64> = (0..6_000).map(|_| rand::random()).collect();
+    let mut filters = Vec::new();
+    for window in &[0..3_000, 2_000..5_000, 4_000..6_000] {
+        let mut bloom = Bloom::new_for_union(10_000, 0.01, seeds);
+        for key in &keys[window.clone()] {
+            bloom.add(key);
+        }
+        filters.push(bloom);
+    }
+    let refs: Vec<&Bloom> = filters.iter().collect();
+    let estimate = Bloom::distinct_estimate_across(&refs).unwrap();
+    assert!(estimate > 5_700 && estimate < 6_300);
+
+    let other = Bloom::new_for_union(10_000, 0.01, (seeds.0.wrapping_add(1), seeds.1));
+    assert!(Bloom::distinct_estimate_across(&[&filters[0], &other]).is_err());
+}
+
+#[test]
+fn bloom_test_apply() {
+    let seeds = (rand::random(), rand::random());
+    let mut replayed = Bloom::new_for_union(100, 0.01, seeds);
+    let mut direct = Bloom::new_for_union(100, 0.01, seeds);
+    let keys: Vec<u64> = (0..4).map(|_| rand::random()).collect();
+    let ops = vec![
+        BloomOp::Check(keys[0]),
+        BloomOp::Add(keys[0]),
+        BloomOp::Check(keys[0]),
+        BloomOp::CheckAndAdd(keys[1]),
+        BloomOp::CheckAndAdd(keys[1]),
+        BloomOp::Check(keys[2]),
+    ];
+    let results: Vec<BloomResult> = ops.into_iter().map(|op| replayed.apply(op)).collect();
+
+    let expected = vec![
+        BloomResult::Checked(direct.check(&keys[0])),
+        {
+            direct.add(&keys[0]);
+            BloomResult::Added
+        },
+        BloomResult::Checked(direct.check(&keys[0])),
+        BloomResult::CheckedAndAdded(direct.check_and_add(&keys[1])),
+        BloomResult::CheckedAndAdded(direct.check_and_add(&keys[1])),
+        BloomResult::Checked(direct.check(&keys[2])),
+    ];
+    assert_eq!(results, expected);
+    assert_eq!(results[2], BloomResult::Checked(true));
+    assert_eq!(replayed.bitmap(), direct.bitmap());
+}
+
+#[test]
+fn bloom_test_content_root() {
+    let seeds = (rand::random(), rand::random());
+    let mut forward = Bloom::new_for_union(100, 0.01, seeds);
+    let mut backward = Bloom::new_for_union(100, 0.01, seeds);
+    let keys: Vec<u64> = (0..10).map(|_| rand::random()).collect();
+    for key in &keys {
+        forward.add(key);
+    }
+    for key in keys.iter().rev() {
+        backward.add(key);
+    }
+    assert_ne!(forward.content_root(), 0);
+    assert_eq!(forward.content_root(), backward.content_root());
+
+    let root = forward.content_root();
+    forward.add(&rand::random::<u64>());
+    assert_ne!(forward.content_root(), root);
+    forward.clear();
+    assert_eq!(forward.content_root(), 0);
+}
+
+#[test]
+fn bloom_test_union_dense_regions() {
+    let mut clean = Bloom::from_existing(&[0; 4], 32, 1, (1, 2));
+    let noisy = Bloom::from_existing(&[0xff, 0xf7, 0x80, 0x01], 32, 1, (1, 2));
+    clean.union_dense_regions(&noisy, 0.5, 16).unwrap();
+    assert_eq!(clean.bitmap().to_bytes(), vec![0xff, 0xf7, 0, 0]);
+    assert_eq!(clean.count_ones(), 15);
+
+    let other = Bloom::from_existing(&[0; 4], 32, 1, (3, 4));
+    assert_eq!(clean.union_dense_regions(&other, 0.5, 16), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_restrict_to() {
+    let mut bloom = Bloom::new(10_000, 1_000);
+    let candidates: Vec<u64> = (0..1_000).map(|_| rand::random()).collect();
+    for key in candidates.iter().step_by(3) {
+        bloom.add(key);
+    }
+    for _ in 0..500 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let restricted = bloom.restrict_to(&candidates);
+    assert!(restricted.number_of_bits() < bloom.number_of_bits());
+    assert!(candidates.iter().all(|key| restricted.check(key) == bloom.check(key)));
+}
+
+#[test]
+fn bloom_test_expected_false_positives() {
+    let mut bloom = Bloom::new_with_rate(1_000, 0.05);
+    for _ in 0..1_000 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let expected = bloom.expected_false_positives(20_000);
+    let observed = (0..20_000).filter(|_| bloom.check(&rand::random::<u64>())).count() as f64;
+    assert!(expected > 700.0 && expected < 1_300.0);
+    assert!((observed - expected).abs() < expected * 0.25);
+}
+
+#[test]
+fn bloom_test_swap_bitmap() {
+    let seeds = (rand::random(), rand::random());
+    let mut live = Bloom::new_for_union(100, 0.01, seeds);
+    let mut rebuilt = Bloom::new_for_union(100, 0.01, seeds);
+    let old_key: u64 = rand::random();
+    let new_key: u64 = rand::random();
+    live.track_recent_inserts(4);
+    live.add(&old_key);
+    rebuilt.add(&new_key);
+    assert_eq!(live.recent_inserts().len(), 1);
+
+    let old = live.swap_bitmap(rebuilt.bitmap(), rebuilt.number_of_bits()).unwrap();
+    assert!(live.recent_inserts().is_empty());
+    assert!(live.check(&new_key));
+    assert!(!live.check(&old_key));
+    assert_eq!(live.count_ones(), rebuilt.count_ones());
+    let restored = Bloom::from_existing(&old.to_bytes(), old.len() as u64, live.number_of_hash_functions(), seeds);
+    assert!(restored.check(&old_key));
+
+    let small = BitVec::from_elem(8, false);
+    assert_eq!(live.swap_bitmap(small, 8), Err(BloomError::SizeMismatch));
+}
+
+#[test]
+fn bloom_test_hash_function_bias() {
+    let bloom = Bloom::new_with_rate(1_000, 0.01);
+    let biases = bloom.hash_function_bias(10_000, 42);
+    assert_eq!(biases.len() as u64, bloom.number_of_hash_functions());
+    assert_eq!(biases[0], 0.0);
+    assert!(biases.iter().all(|&bias| bias < 0.01));
+
+    let tiny = Bloom::from_existing(&[0], 8, 4, (1, 2));
+    assert!(tiny.hash_function_bias(1_000, 42)[3] > 0.1);
+}
+
+#[test]
+fn bloom_test_from_hash_pairs() {
+    let seeds = (rand::random(), rand::random());
+    let keys: Vec<u64> = (0..100).map(|_| rand::random()).collect();
+    let mut added = Bloom::new_for_union(100, 0.01, seeds);
+    for key in &keys {
+        added.add(key);
+    }
+    let bitmap_size = Bloom::compute_bitmap_size(100, 0.01);
+    let pairs: Vec<(u64, u64)> = keys.iter().map(|key| added.hash_pair(key)).collect();
+    let built = Bloom::from_hash_pairs(bitmap_size, 100, seeds, pairs.into_iter());
+    assert!(built.is_compatible(&added));
+    assert_eq!(built.bitmap(), added.bitmap());
+    assert_eq!(built.content_root(), added.content_root());
+    assert!(keys.iter().all(|key| built.check(key)));
+}
+
+#[test]
+fn bloom_test_to_bytes() {
+    let mut original = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    original.add(&key);
+    let bytes = original.to_bytes();
+    assert_eq!(&bytes[..4], b"XXBF");
+    let loaded = Bloom::from_bytes(&bytes).unwrap();
+    assert!(loaded.is_compatible(&original));
+    assert!(loaded.check(&key));
+
+    assert!(Bloom::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert_eq!(Bloom::from_bytes(b"not a filter").err(), Some(BloomError::InvalidFormat));
+}
+
+#[test]
+fn bloom_test_from_bytes_scheme_mismatch() {
+    let mut bytes = Bloom::new(100, 10).to_bytes();
+    bytes[5] = 0xfe;
+    assert_eq!(Bloom::from_bytes(&bytes).err(), Some(BloomError::SchemeMismatch(0xfe)));
+}
+
+#[test]
+fn bloom_test_from_bytes_invalid_k() {
+    let mut bytes = Bloom::new(100, 10).to_bytes();
+    let k_range = Bloom::layout().k;
+    bytes[k_range.clone()].copy_from_slice(&0u64.to_le_bytes());
+    assert_eq!(Bloom::from_bytes(&bytes).err(), Some(BloomError::InvalidFormat));
+    bytes[k_range.clone()].copy_from_slice(&(MAX_UNTRUSTED_K + 1).to_le_bytes());
+    assert_eq!(Bloom::from_bytes(&bytes).err(), Some(BloomError::InvalidFormat));
+    bytes[k_range].copy_from_slice(&MAX_UNTRUSTED_K.to_le_bytes());
+    assert_eq!(Bloom::from_bytes(&bytes).map(|bloom| bloom.number_of_hash_functions()), Ok(MAX_UNTRUSTED_K));
+}
+
+#[test]
+fn bloom_test_novelty_fraction() {
+    let mut bloom = Bloom::new_with_rate(1_000, 0.01);
+    let batch: Vec<u64> = (0..1_000).map(|_| rand::random()).collect();
+    for key in &batch[..500] {
+        bloom.add(key);
+    }
+    let novelty = bloom.novelty_fraction(&batch);
+    assert!(novelty > 0.48 && novelty <= 0.5);
+    assert_eq!(bloom.novelty_fraction::<u64>(&[]), 0.0);
+}
+
+#[test]
+fn bloom_test_new_guard() {
+    let cheap = Bloom::new_guard(1_000, 10.0);
+    let expensive = Bloom::new_guard(1_000, 10_000.0);
+    assert!(expensive.number_of_bits() > cheap.number_of_bits());
+    assert!(expensive.number_of_hash_functions() >= cheap.number_of_hash_functions());
+    assert_eq!(
+        expensive.number_of_bits(),
+        Bloom::new_with_rate(1_000, 1.0 / (10_000.0 * f64::consts::LN_2 * f64::consts::LN_2)).number_of_bits()
+    );
+    assert_eq!(Bloom::new_guard(1_000, 0.1).number_of_bits(), Bloom::new_with_rate(1_000, 0.5).number_of_bits());
+}
+
+#[test]
+fn bloom_test_to_columns() {
+    let mut original = Bloom::new(100, 10);
+    let key: u64 = rand::random();
+    original.add(&key);
+    let (bitmap_size, k, seeds, bitmap) = original.to_columns().unwrap();
+    assert_eq!(bitmap_size, original.number_of_bits());
+    assert_eq!(bitmap.len(), 100);
+    let loaded = Bloom::from_existing(&bitmap, bitmap_size, k, seeds);
+    assert!(loaded.is_compatible(&original));
+    assert_eq!(loaded.bitmap(), original.bitmap());
+    assert!(loaded.check(&key));
+}
+
+#[test]
+fn bloom_test_min_count_estimate() {
+    let below = (0..50)
+        .filter(|_| {
+            let mut bloom = Bloom::new_with_rate(1_000, 0.01);
+            for _ in 0..1_000 {
+                bloom.add(&rand::random::<u64>());
+            }
+            let bound = bloom.min_count_estimate(0.95);
+            assert!(bound < bloom.estimate_count());
+            bound <= 1_000
+        })
+        .count();
+    assert!(below >= 45);
+    assert!((Bloom::normal_quantile(0.975) - 1.96).abs() < 1e-3);
+}
+
+#[test]
+fn bloom_test_try_union_bounded() {
+    let seeds = (rand::random(), rand::random());
+    let mut aggregate = Bloom::new_for_union(1_000, 0.01, seeds);
+    let mut merged = 0;
+    for _ in 0..10 {
+        let mut shard = Bloom::new_for_union(1_000, 0.01, seeds);
+        for _ in 0..200 {
+            shard.add(&rand::random::<u64>());
+        }
+        let before = aggregate.bitmap();
+        if aggregate.try_union_bounded(&shard, 0.4).unwrap() {
+            merged += 1;
+        } else {
+            assert_eq!(aggregate.bitmap(), before);
+        }
+        assert!(aggregate.fill_ratio() <= 0.4);
+    }
+    assert!((2..10).contains(&merged));
+
+    let other = Bloom::new(100, 10);
+    assert_eq!(aggregate.try_union_bounded(&other, 1.0), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_new_for_histogram() {
+    let uniform = Bloom::new_for_histogram(&[(0..10_000, 1_000.0), (10_000..20_000, 1_000.0)], 0.01);
+    let skewed = Bloom::new_for_histogram(&[(0..2_000, 1_800.0), (2_000..20_000, 200.0)], 0.01);
+    assert!(skewed.number_of_bits() < uniform.number_of_bits());
+    let classic = Bloom::new_with_rate(2_000, 0.01);
+    assert!(uniform.number_of_bits() < classic.number_of_bits());
+}
+
+#[test]
+fn bloom_test_adversarial_fp_estimate() {
+    let mut bloom = Bloom::new_with_rate(1_000, 0.01);
+    for _ in 0..1_000 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let adversarial = bloom.adversarial_fp_estimate(100, 42);
+    assert!(adversarial > 0.9);
+    assert!(adversarial > 10.0 * bloom.current_fp_rate());
+    assert_eq!(Bloom::new(100, 10).adversarial_fp_estimate(10, 42), 0.0);
+}
+
+#[test]
+fn bloom_test_word_popcounts() {
+    let mut bloom = Bloom::new(100, 10);
+    for _ in 0..10 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let popcounts = bloom.word_popcounts();
+    assert_eq!(popcounts.len(), 13);
+    assert_eq!(popcounts.iter().map(|&ones| u64::from(ones)).sum::<u64>(), bloom.count_ones());
+
+    let bloom = Bloom::from_existing(&[0xff, 0, 0, 0, 0, 0, 0, 0x01, 0x03], 72, 1, (1, 2));
+    assert_eq!(bloom.word_popcounts(), vec![9, 2]);
 }
 
 #[test]
-fn bloom_test_clear() {
+fn bloom_test_new_verified() {
+    let keys: Vec<u64> = (0..1_000).collect();
+    let bloom = Bloom::new_verified(&keys, 0.02, 30).unwrap();
+    assert!(keys.iter().all(|key| bloom.check(key)));
+    let false_positives = (1_000..11_000u64).filter(|key| bloom.check(key)).count();
+    assert!(false_positives < 400);
+    assert!(Bloom::new_verified(&keys, 0.02, 0).is_none());
+}
+
+#[test]
+fn bloom_test_union_streams() {
+    let seeds = (rand::random(), rand::random());
+    let mut first = Bloom::new_for_union(10_000, 0.01, seeds);
+    let mut second = Bloom::new_for_union(10_000, 0.01, seeds);
+    for _ in 0..5_000 {
+        first.add(&rand::random::<u64>());
+        second.add(&rand::random::<u64>());
+    }
+    let mut out = Vec::new();
+    Bloom::union_streams(&mut first.to_bytes().as_slice(), &mut second.to_bytes().as_slice(), &mut out).unwrap();
+    first.union(&second).unwrap();
+    assert_eq!(out, first.to_bytes());
+
+    let other = Bloom::new(100, 10).to_bytes();
+    let err = Bloom::union_streams(&mut out.as_slice(), &mut other.as_slice(), &mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let truncated = &out[..out.len() - 1];
+    let err = Bloom::union_streams(&mut out.as_slice(), &mut &truncated[..], &mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn bloom_test_current_bits_per_item() {
+    let mut bloom = Bloom::new_with_rate(10_000, 0.01);
+    assert_eq!(bloom.current_bits_per_item(), None);
+    for _ in 0..5_000 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let expected = bloom.number_of_bits() as f64 / 5_000.0;
+    assert!((bloom.current_bits_per_item().unwrap() - expected).abs() < expected * 0.05);
+}
+
+#[test]
+fn bloom_test_new_in_buffer() {
+    let seeds = (rand::random(), rand::random());
+    let mut arena = vec![0u8; 200];
+    let first_key: u64 = rand::random();
+    let second_key: u64 = rand::random();
+    {
+        let mut first = Bloom::new_in_buffer(&mut arena, 0, 800, 4, seeds).unwrap();
+        first.add(&first_key);
+        assert!(first.check(&first_key));
+    }
+    {
+        let mut second = Bloom::new_in_buffer(&mut arena, 100, 800, 4, seeds).unwrap();
+        assert!(!second.check(&first_key));
+        second.add(&second_key);
+    }
+    let first = Bloom::new_in_buffer(&mut arena, 0, 800, 4, seeds).unwrap();
+    assert!(first.check(&first_key));
+    assert!(!first.check(&second_key));
+
+    let owned = Bloom::from_existing(&arena[100..], 800, 4, seeds);
+    assert!(owned.check(&second_key));
+    assert!(owned.count_ones() > 0 && owned.count_ones() <= 4);
+    assert_eq!(Bloom::new_in_buffer(&mut arena, 101, 800, 4, seeds).err(), Some(BloomError::BufferTooSmall));
+}
+
+#[test]
+fn bloom_test_changed_since() {
+    let mut bloom = Bloom::new(100, 10);
+    bloom.add(&rand::random::<u64>());
+    let snapshot = Bloom::from_existing_struct(&bloom);
+    assert_eq!(bloom.changed_since(&snapshot), Ok(false));
+    bloom.add(&rand::random::<u64>());
+    assert_eq!(bloom.changed_since(&snapshot), Ok(true));
+    assert_eq!(snapshot.changed_since(&bloom), Ok(false));
+    assert_eq!(bloom.changed_since(&Bloom::new(100, 10)), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_cache_miss_estimate() {
+    let small = Bloom::new_capped_k(64, 10, 7);
+    let large = Bloom::new_capped_k(1_000_000, 100_000, 7);
+    assert_eq!(small.cache_miss_estimate(64), 1.0);
+    let blocked = 1.0;
+    let estimate = large.cache_miss_estimate(64);
+    assert!(estimate > blocked);
+    assert!(estimate > 6.9 && estimate <= 7.0);
+    assert!(Bloom::new_capped_k(1_000, 100, 7).cache_miss_estimate(64) < estimate);
+}
+
+#[test]
+fn bloom_test_new_balanced() {
+    let balanced = Bloom::new_balanced(10, 0.1, 50).effective_k_variance(1_000, 0);
+    let arbitrary: f64 = (0..10)
+        .map(|_| Bloom::new_with_rate(10, 0.1).effective_k_variance(1_000, 0))
+        .sum::<f64>()
+        / 10.0;
+    assert!(balanced < arbitrary);
+}
+
+#[test]
+fn bloom_test_additional_bits_for() {
+    let mut bloom = Bloom::new_with_rate(10_000, 0.01);
+    assert_eq!(bloom.additional_bits_for(5_000), 0);
+    for _ in 0..10_000 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let fresh = Bloom::new_with_rate(15_000, 0.01);
+    let expected = (fresh.number_of_bits() - bloom.number_of_bits()) as f64;
+    let additional = bloom.additional_bits_for(5_000) as f64;
+    assert!((additional - expected).abs() < expected * 0.05);
+}
+
+#[test]
+fn bloom_test_from_density_map() {
+    let bloom = Bloom::from_density_map(&[1.0, 0.0, 0.25], 64, (1, 2), 3);
+    assert_eq!(bloom.number_of_bits(), 192);
+    assert_eq!(bloom.count_ones(), 80);
+    assert_eq!(bloom.fill_histogram(3), vec![1.0, 0.0, 0.25]);
+    assert_eq!(bloom.fill_histogram(6)[..4], [1.0, 1.0, 0.0, 0.0]);
+}
+
+#[test]
+fn bloom_test_layout() {
+    let bloom = Bloom::new(100, 10);
+    let bytes = bloom.to_bytes();
+    let layout = Bloom::layout();
+    assert_eq!(bytes[layout.magic], MAGIC);
+    assert_eq!(bytes[layout.version], [FORMAT_VERSION]);
+    assert_eq!(bytes[layout.scheme], [HASH_SCHEME]);
+    assert_eq!(bytes[layout.bitmap_size], bloom.number_of_bits().to_le_bytes());
+    assert_eq!(bytes[layout.k], bloom.number_of_hash_functions().to_le_bytes());
+    let mut seeds = bloom.seeds().0.to_le_bytes().to_vec();
+    seeds.extend_from_slice(&bloom.seeds().1.to_le_bytes());
+    assert_eq!(bytes[layout.seeds], seeds[..]);
+    assert_eq!(bytes[layout.bitmap_offset..], bloom.bitmap().to_bytes()[..]);
+}
+
+#[test]
+fn bloom_test_add_throttled() {
+    let mut bloom = Bloom::new_with_rate(1_000, 0.01);
+    let outcomes: Vec<AddOutcome> = (0..3_000).map(|_| bloom.add_throttled(&rand::random::<u64>())).collect();
+    let first_throttled = outcomes
+        .iter()
+        .position(|&outcome| outcome == AddOutcome::ThrottledWouldExceedFp)
+        .unwrap();
+    assert!(first_throttled > 900 && first_throttled < 1_100);
+    assert!(outcomes[first_throttled..].iter().all(|&outcome| outcome != AddOutcome::Inserted));
+    assert!(bloom.fill_ratio() > 0.49 && bloom.fill_ratio() < 0.51);
+
     let mut bloom = Bloom::new(100, 10);
     let key: u64 = rand::random();
-    bloom.add(&key);
-    assert_eq!(bloom.check(&key), true);
+    assert_eq!(bloom.add_throttled(&key), AddOutcome::Inserted);
+    assert_eq!(bloom.add_throttled(&key), AddOutcome::AlreadyPresent);
+}
+
+#[test]
+fn bloom_test_approximate_from_stats() {
+    let bloom = Bloom::approximate_from_stats(1_000, 3, (1, 2), 400, 42);
+    assert_eq!(bloom.number_of_bits(), 1_000);
+    assert_eq!(bloom.count_ones(), 400);
+    assert_eq!(bloom.bitmap().iter().filter(|bit| *bit).count(), 400);
+    assert_eq!(Bloom::approximate_from_stats(1_000, 3, (1, 2), 400, 42).bitmap(), bloom.bitmap());
+}
+
+#[test]
+fn bloom_test_corrected_fp_rate() {
+    let (bitmap_size, k, items_count) = (256, 20, 4);
+    let mut false_positives = 0;
+    let trials = 200;
+    let queries = 1_000;
+    let mut corrected = 0.0;
+    for _ in 0..trials {
+        let mut bloom = Bloom::from_existing(&[0; 32], bitmap_size, k, (rand::random(), rand::random()));
+        for _ in 0..items_count {
+            bloom.add(&rand::random::<u64>());
+        }
+        false_positives += (0..queries).filter(|_| bloom.check(&rand::random::<u64>())).count();
+        corrected += bloom.corrected_fp_rate(items_count) / trials as f64;
+    }
+    let empirical = false_positives as f64 / (trials * queries) as f64;
+    let textbook = Bloom::false_positive_rate(bitmap_size, k, items_count as f64);
+    assert!((corrected - empirical).abs() < (textbook - empirical).abs());
+}
+
+#[test]
+fn bloom_test_multi_bloom() {
+    let seeds = (rand::random(), rand::random());
+    let configs = [(100, 0.1), (1_000, 0.01), (10_000, 0.001)];
+    let mut multi = MultiBloom::new(&configs, seeds);
+    let mut independent: Vec<Bloom> = configs
+        .iter()
+        .map(|&(items_count, fp_p)| Bloom::new_for_union(items_count, fp_p, seeds))
+        .collect();
+    let keys: Vec<u64> = (0..500).map(|_| rand::random()).collect();
+    for key in &keys {
+        multi.add(key);
+        for bloom in &mut independent {
+            bloom.add(key);
+        }
+    }
+    for (shared, bloom) in multi.filters().iter().zip(&independent) {
+        assert_eq!(shared.bitmap(), bloom.bitmap());
+    }
+    for key in keys.iter().take(10).chain(&[1, 2, 3]) {
+        let expected: Vec<bool> = independent.iter().map(|bloom| bloom.check(key)).collect();
+        assert_eq!(multi.check(key), expected);
+    }
+}
+
+#[test]
+fn bloom_test_compressibility() {
+    let mut sparse = Bloom::new(10_000, 10_000);
+    for _ in 0..10 {
+        sparse.add(&rand::random::<u64>());
+    }
+    assert!(sparse.compressibility() < 0.05);
+    let half = Bloom::approximate_from_stats(80_000, 1, (1, 2), 40_000, 42);
+    assert!(half.compressibility() > 0.95);
+    assert_eq!(Bloom::new(100, 10).compressibility(), 13.0 / 800.0);
+}
+
+#[test]
+fn bloom_test_verify_union() {
+    let seeds = (rand::random(), rand::random());
+    let mut a = Bloom::new_for_union(1_000, 0.01, seeds);
+    let mut b = Bloom::new_for_union(1_000, 0.01, seeds);
+    let keys_a: Vec<u64> = (0..100).map(|_| rand::random()).collect();
+    let keys_b: Vec<u64> = (0..100).map(|_| rand::random()).collect();
+    keys_a.iter().for_each(|key| a.add(key));
+    keys_b.iter().for_each(|key| b.add(key));
+
+    let mut union = Bloom::from_existing_struct(&a);
+    union.union(&b).unwrap();
+    assert!(Bloom::verify_union(&a, &b, &union, &keys_a, &keys_b));
+    let broken = Bloom::from_existing_struct(&a);
+    assert!(!Bloom::verify_union(&a, &b, &broken, &keys_a, &keys_b));
+}
+
+#[test]
+fn bloom_test_expected_bits_per_insert() {
+    let mut bloom = Bloom::new_with_rate(10_000, 0.01);
+    assert_eq!(bloom.expected_bits_per_insert(), bloom.number_of_hash_functions() as f64);
+    for _ in 0..5_000 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let expected = bloom.expected_bits_per_insert();
+    let before = bloom.count_ones();
+    for _ in 0..500 {
+        bloom.add(&rand::random::<u64>());
+    }
+    let observed = (bloom.count_ones() - before) as f64 / 500.0;
+    assert!((observed - expected).abs() < expected * 0.1);
+}
+
+#[test]
+fn bloom_test_health() {
+    let mut bloom = Bloom::new(10, 10);
+    assert_eq!(bloom.health(), Health::Empty);
+    bloom.add(&rand::random::<u64>());
+    assert_eq!(bloom.health(), Health::Normal);
+    let mut inserted = 0;
+    while bloom.health() == Health::Normal {
+        bloom.add(&rand::random::<u64>());
+        inserted += 1;
+    }
+    assert_eq!(bloom.health(), Health::Saturated);
+    assert!(inserted > 10);
     bloom.clear();
-    assert_eq!(bloom.check(&key), false);
+    assert_eq!(bloom.health(), Health::Empty);
 }
 
 #[test]
-fn bloom_test_load() {
-    let mut original = Bloom::new(100, 10);
-    let key: u64 = rand::random();
-    original.add(&key);
-    assert_eq!(original.check(&key), true);
+fn bloom_test_from_membership_oracle() {
+    let candidates: Vec<u64> = (0..1_000).collect();
+    let bloom = Bloom::from_membership_oracle(&candidates, |key| key % 2 == 0, 0.01);
+    assert_eq!(bloom.insertions(), 500);
+    assert!(candidates.iter().filter(|key| *key % 2 == 0).all(|key| bloom.check(key)));
+    assert!(candidates.iter().filter(|key| *key % 2 == 1 && bloom.check(key)).count() < 25);
+}
 
-    let cloned = Bloom::from_existing(
-        &original.bitmap().to_bytes(),
-        original.number_of_bits(),
-        original.number_of_hash_functions(),
-        original.xx(),
-    );
-    assert_eq!(cloned.check(&key), true);
+#[test]
+fn bloom_test_min_k_to_distinguish() {
+    let bloom = Bloom::new(100, 10);
+    let (a, b): (u64, u64) = (rand::random(), rand::random());
+    let k = bloom.min_k_to_distinguish(&a, &b).unwrap();
+    assert!(k <= 2);
+    assert_eq!(bloom.min_k_to_distinguish(&a, &a), None);
+
+    let tiny = Bloom::from_existing(&[0], 8, 1, (1, 2));
+    let colliding = (0..1_000u64)
+        .find(|key| tiny.probe_offsets(key) == tiny.probe_offsets(&0u64) && *key != 0)
+        .unwrap();
+    assert_ne!(tiny.min_k_to_distinguish(&0u64, &colliding), Some(1));
 }
 
 #[test]
-fn bloom_test_load_struct() {
-    let mut original = Bloom::new(100, 10);
-    let key: u64 = rand::random();
-    original.add(&key);
-    assert_eq!(original.check(&key), true);
+fn bloom_test_to_buffer() {
+    let mut bloom = Bloom::new(13, 10);
+    for key in 0..10u64 {
+        bloom.add(&key);
+    }
+    let expected = bloom.to_bytes();
+    let mut buffer = [0xffu8; 64];
+    let written = bloom.to_buffer(&mut buffer[..expected.len()]).unwrap();
+    assert_eq!(written, expected.len());
+    assert_eq!(&buffer[..written], &expected[..]);
+    assert_eq!(bloom.to_buffer(&mut buffer[..expected.len() - 1]), Err(BloomError::BufferTooSmall));
+}
 
-    let cloned = Bloom::from_existing_struct(&original);
-    assert_eq!(cloned.check(&key), true);
+#[test]
+fn bloom_test_window_union() {
+    let seeds = (11, 12);
+    let mut windows = VecDeque::new();
+    for minute in 0..4u64 {
+        let mut bloom = Bloom::new_for_union(100, 0.01, seeds);
+        for key in minute * 100..minute * 100 + 100 {
+            bloom.add(&key);
+        }
+        windows.push_back(bloom);
+        if windows.len() > 3 {
+            windows.pop_front();
+        }
+    }
+    let union = Bloom::window_union(&windows).unwrap();
+    assert!((100..400u64).all(|key| union.check(&key)));
+    assert_eq!(union.insertions(), 300);
+
+    windows.push_back(Bloom::new_for_union(100, 0.01, (13, 14)));
+    assert_eq!(Bloom::window_union(&windows).err(), Some(BloomError::Incompatible));
+    assert_eq!(Bloom::window_union(&VecDeque::new()).err(), Some(BloomError::Incompatible));
+
+    let unaligned: VecDeque<Bloom> = (0..3).map(|rng_seed| Bloom::random(100, 3, seeds, 0.1, rng_seed)).collect();
+    let union = Bloom::window_union(&unaligned).unwrap();
+    assert_eq!(union.bitmap().len(), 100);
+    for (i, bit) in union.bitmap().iter().enumerate() {
+        assert_eq!(bit, unaligned.iter().any(|bloom| bloom.bitmap()[i]));
+    }
+}
+
+#[test]
+fn bloom_test_fp_independence() {
+    let mut first = Bloom::new_for_union(100, 0.2, (1, 2));
+    let mut same_seeds = Bloom::new_for_union(100, 0.2, (1, 2));
+    let mut other_seeds = Bloom::new_for_union(100, 0.2, (3, 4));
+    for key in 0..100u64 {
+        first.add(&key);
+        same_seeds.add(&key);
+        other_seeds.add(&key);
+    }
+    assert!(first.fp_independence(&same_seeds, 10_000, 5) > 0.99);
+    assert!(first.fp_independence(&other_seeds, 10_000, 5).abs() < 0.1);
+}
+
+#[test]
+fn bloom_test_new_bounded_probes() {
+    let (bloom, fp_rate) = Bloom::new_bounded_probes(1_000, 1_200, 3).unwrap();
+    assert!(bloom.number_of_bits() <= 1_200 * 8);
+    assert_eq!(bloom.number_of_hash_functions(), 3);
+    assert_eq!(fp_rate, bloom.corrected_fp_rate(1_000));
+    assert!((0.005..0.04).contains(&fp_rate));
+
+    let (roomy, fp_rate) = Bloom::new_bounded_probes(10, 1 << 20, 4).unwrap();
+    assert!(roomy.number_of_bits() < 1 << 20);
+    assert!(roomy.number_of_hash_functions() <= 4);
+    assert!(fp_rate <= BOUNDED_PROBES_MIN_FP);
+    let (capped, fp_rate) = Bloom::new_bounded_probes(10, 1 << 20, 3).unwrap();
+    assert_eq!(capped.number_of_hash_functions(), 3);
+    assert!(capped.number_of_bits() < 1 << 20);
+    assert!(fp_rate <= BOUNDED_PROBES_MIN_FP);
+    assert_eq!(Bloom::new_bounded_probes(1_000, 10, 4).err(), Some(BloomError::Infeasible));
+    assert_eq!(Bloom::new_bounded_probes(1_000, 0, 4).err(), Some(BloomError::Infeasible));
+}
+
+#[test]
+fn bloom_test_delta_encoded() {
+    let mut bloom = Bloom::new(10_000, 10_000);
+    for key in 0..20u64 {
+        bloom.add(&key);
+    }
+    let encoded = bloom.to_delta_encoded().unwrap();
+    assert!(encoded.len() * 10 < bloom.to_bytes().len());
+
+    let decoded = Bloom::from_delta_encoded(&encoded).unwrap();
+    assert_eq!(decoded.bitmap().to_bytes(), bloom.bitmap().to_bytes());
+    assert_eq!(decoded.seeds(), bloom.seeds());
+    assert_eq!(decoded.number_of_hash_functions(), bloom.number_of_hash_functions());
+    assert!((0..20u64).all(|key| decoded.check(&key)));
+
+    let mut out_of_range = encoded[..32].to_vec();
+    out_of_range.extend_from_slice(&[0xff, 0xff, 0x7f]);
+    assert_eq!(Bloom::from_delta_encoded(&out_of_range).err(), Some(BloomError::InvalidFormat));
+    assert_eq!(Bloom::from_delta_encoded(&encoded[..16]).err(), Some(BloomError::InvalidFormat));
+    let mut truncated = encoded.clone();
+    truncated.push(0x80);
+    assert_eq!(Bloom::from_delta_encoded(&truncated).err(), Some(BloomError::InvalidFormat));
+
+    for &bitmap_size in &[u64::MAX, MAX_UNTRUSTED_BITS + 1, 0] {
+        let mut malicious = encoded[..32].to_vec();
+        malicious[..8].copy_from_slice(&bitmap_size.to_le_bytes());
+        assert_eq!(Bloom::from_delta_encoded(&malicious).err(), Some(BloomError::InvalidFormat));
+    }
+}
+
+#[test]
+fn bloom_test_recent_inserts() {
+    let mut bloom = Bloom::new(100, 10);
+    bloom.add(&0u64);
+    assert!(bloom.recent_inserts().is_empty());
+
+    bloom.track_recent_inserts(3);
+    for key in 1..10u64 {
+        bloom.add(&key);
+        let tracked = cmp::min(key, 3) as usize;
+        assert_eq!(bloom.recent_inserts().len(), tracked);
+    }
+    let expected: Vec<_> = (7..10u64).map(|key| bloom.hash_pair(&key)).collect();
+    assert_eq!(bloom.recent_inserts(), &expected[..]);
+
+    bloom.clear();
+    assert!(bloom.recent_inserts().is_empty());
+    bloom.track_recent_inserts(0);
+    bloom.add(&1u64);
+    assert!(bloom.recent_inserts().is_empty());
+}
+
+#[test]
+fn bloom_test_new_with_hashers() {
+    fn fnv1a(basis: u64, bytes: &[u8]) -> u64 {
+        bytes.iter().fold(basis, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3))
+    }
+    let build = || {
+        Bloom::new_with_hashers(
+            100,
+            10,
+            |bytes: &[u8]| fnv1a(0xcbf2_9ce4_8422_2325, bytes),
+            |bytes: &[u8]| fnv1a(0x8422_2325_cbf2_9ce4, bytes) | 1,
+        )
+    };
+    let mut bloom = build();
+    for key in 0..10u64 {
+        bloom.add(&key);
+    }
+    assert!((0..10u64).all(|key| bloom.check(&key)));
+    assert_eq!(bloom.hash_pair(&3u64), build().hash_pair(&3u64));
+    assert_eq!(bloom.probe_offsets(&3u64), build().probe_offsets(&3u64));
+    assert_eq!(bloom.hash_pair(&3u64).0, fnv1a(0xcbf2_9ce4_8422_2325, &3u64.to_ne_bytes()));
+
+    assert!(!bloom.is_compatible(&build()));
+    assert_eq!(bloom.union(&build()).err(), Some(BloomError::Incompatible));
+    assert_eq!(Bloom::from_bytes(&bloom.to_bytes()).err(), Some(BloomError::SchemeMismatch(CUSTOM_HASH_SCHEME)));
+    let mut buffer = vec![0u8; bloom.to_bytes().len()];
+    bloom.to_buffer(&mut buffer).unwrap();
+    assert_eq!(Bloom::from_bytes(&buffer).err(), Some(BloomError::SchemeMismatch(CUSTOM_HASH_SCHEME)));
+    assert_eq!(SerdeBloom::try_from(&bloom).err().map(|err| err.to_string()), Some(BloomError::CustomHashers.to_string()));
+    assert_eq!(bloom.to_delta_encoded(), Err(BloomError::CustomHashers));
+    assert_eq!(bloom.to_columns(), Err(BloomError::CustomHashers));
+    let (mut meta, mut bitmap) = (Vec::new(), Vec::new());
+    let err = bloom.to_split(&mut meta, &mut bitmap).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(meta.is_empty() && bitmap.is_empty());
+}
+
+#[test]
+fn bloom_test_optimal_rotation_interval() {
+    let mut bloom = Bloom::new_with_rate(10_000, 0.01);
+    let capacity = bloom.items_until_fp(0.01);
+    assert!((9_000..11_000).contains(&capacity));
+
+    let slow = bloom.optimal_rotation_interval(100.0, 0.01);
+    let fast = bloom.optimal_rotation_interval(1_000.0, 0.01);
+    assert!(fast < slow);
+    assert!((90.0..110.0).contains(&slow.as_secs_f64()));
+
+    for key in 0..5_000u64 {
+        bloom.add(&key);
+    }
+    assert!(bloom.optimal_rotation_interval(100.0, 0.01) < slow);
+    assert!(bloom.items_until_fp(0.001) < bloom.items_until_fp(0.01));
+    assert_eq!(bloom.optimal_rotation_interval(1e-300, 0.01), Duration::MAX);
+}
+
+#[test]
+fn bloom_test_minhash_signature() {
+    let seeds = (5, 6);
+    let build = |keys: Range<u64>| {
+        let mut bloom = Bloom::new_for_union(1_000, 0.01, seeds);
+        for key in keys {
+            bloom.add(&key);
+        }
+        bloom
+    };
+    let agreement = |a: &[u64], b: &[u64]| a.iter().zip(b).filter(|(x, y)| x == y).count() as f64 / a.len() as f64;
+    let reference = build(0..1_000).minhash_signature(200);
+    assert_eq!(reference, build(0..1_000).minhash_signature(200));
+    let similar = agreement(&reference, &build(50..1_050).minhash_signature(200));
+    let disjoint = agreement(&reference, &build(5_000..6_000).minhash_signature(200));
+    assert!(similar > 0.7);
+    assert!(disjoint < similar - 0.3);
+    assert_eq!(Bloom::new(10, 10).minhash_signature(3), vec![u64::MAX; 3]);
+}
+
+#[test]
+fn bloom_test_seal() {
+    let mut bloom = Bloom::new_with_rate(100, 0.01);
+    bloom.track_recent_inserts(10);
+    for key in 0..100u64 {
+        bloom.add(&key);
+    }
+    let (bits, k, seeds, ones) = (bloom.number_of_bits(), bloom.number_of_hash_functions(), bloom.seeds(), bloom.count_ones());
+    let fp_rate = bloom.current_fp_rate();
+    let answers: Vec<bool> = (0..1_000u64).map(|key| bloom.check(&key)).collect();
+
+    let sealed = bloom.seal();
+    assert_eq!(answers, (0..1_000u64).map(|key| sealed.check(&key)).collect::<Vec<_>>());
+    assert_eq!((sealed.number_of_bits(), sealed.number_of_hash_functions(), sealed.seeds()), (bits, k, seeds));
+    assert_eq!((sealed.count_ones(), sealed.insertions()), (ones, 100));
+    assert_eq!(sealed.current_fp_rate(), fp_rate);
+    assert_eq!(sealed.bitmap().len() as u64, bits);
+}
+
+#[test]
+fn bloom_test_likely_disjoint() {
+    let seeds = (21, 22);
+    let build = |keys: Range<u64>| {
+        let mut bloom = Bloom::new_for_union(1_000, 0.01, seeds);
+        for key in keys {
+            bloom.add(&key);
+        }
+        bloom
+    };
+    let first = build(0..500);
+    assert_eq!(first.likely_disjoint(&build(500..1_000)), Ok(true));
+    assert_eq!(first.likely_disjoint(&build(250..750)), Ok(false));
+    assert_eq!(first.likely_disjoint(&first), Ok(false));
+    let other_seeds = Bloom::new_for_union(1_000, 0.01, (1, 2));
+    assert_eq!(first.likely_disjoint(&other_seeds), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_relative_load() {
+    let build = |count: u64| {
+        let mut bloom = Bloom::new_with_rate(10_000, 0.01);
+        for key in 0..count {
+            bloom.add(&key);
+        }
+        bloom
+    };
+    let hot = build(5_000);
+    let peers = [build(1_000), build(1_000), build(1_000)];
+    let peers: Vec<&Bloom> = peers.iter().collect();
+    let load = hot.relative_load(&peers);
+    assert!((4.5..5.5).contains(&load));
+    assert!(peers[0].relative_load(&[&hot]) < 1.0);
+    assert_eq!(build(0).relative_load(&[&build(0)]), 1.0);
+    assert_eq!(hot.relative_load(&[&build(0)]), f64::INFINITY);
+    assert_eq!(hot.relative_load(&[]), 1.0);
+}
+
+#[test]
+fn bloom_test_random() {
+    let bloom = Bloom::random(10_000, 4, (1, 2), 0.3, 7);
+    let again = Bloom::random(10_000, 4, (1, 2), 0.3, 7);
+    assert_eq!(bloom.bitmap().to_bytes(), again.bitmap().to_bytes());
+    assert_eq!(bloom.count_ones(), again.count_ones());
+    assert!((2_700..3_300).contains(&bloom.count_ones()));
+    assert_eq!((bloom.number_of_bits(), bloom.number_of_hash_functions(), bloom.seeds()), (10_000, 4, (1, 2)));
+    assert_ne!(Bloom::random(10_000, 4, (1, 2), 0.3, 8).bitmap().to_bytes(), bloom.bitmap().to_bytes());
+    assert_eq!(Bloom::random(100, 4, (1, 2), 0.0, 7).count_ones(), 0);
+}
+
+#[test]
+fn bloom_test_peek_version() {
+    let mut bytes = Bloom::new(10, 10).to_bytes();
+    assert_eq!(Bloom::peek_version(&bytes), Ok(FORMAT_VERSION));
+    bytes[VERSION_OFFSET] = 2;
+    assert_eq!(Bloom::peek_version(&bytes[..VERSION_OFFSET + 1]), Ok(2));
+    assert_eq!(Bloom::from_bytes(&bytes).err(), Some(BloomError::UnsupportedVersion(2)));
+    assert_eq!(Bloom::peek_version(b"not a bloom filter"), Err(BloomError::InvalidFormat));
+    assert_eq!(Bloom::peek_version(&MAGIC), Err(BloomError::InvalidFormat));
+}
+
+#[test]
+fn bloom_test_plan_merge_order() {
+    let peak = |sizes: &[usize], order: &[usize]| {
+        let mut accumulated = sizes[order[0]];
+        let mut peak = accumulated;
+        for &i in &order[1..] {
+            let materialized = if sizes[i] > accumulated { sizes[i] } else { 0 };
+            peak = cmp::max(peak, accumulated + sizes[i] + materialized);
+            accumulated = cmp::max(accumulated, sizes[i]);
+        }
+        peak
+    };
+    let sizes = [64, 1 << 20, 128, 1 << 10, 256, 1 << 16];
+    let order = Bloom::plan_merge_order(&sizes);
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..sizes.len()).collect::<Vec<_>>());
+    let naive: Vec<usize> = (0..sizes.len()).collect();
+    assert_eq!(peak(&sizes, &order), (1 << 20) + (1 << 16));
+    assert!(peak(&sizes, &order) < peak(&sizes, &naive));
+    assert_eq!(Bloom::plan_merge_order(&[]), Vec::<usize>::new());
+}
+
+#[test]
+fn bloom_test_rle() {
+    let rle = [(false, 10), (true, 3), (false, 50), (true, 1), (false, 0), (true, 1), (false, 15)];
+    let bloom = Bloom::from_rle(&rle, 80, 3, (1, 2)).unwrap();
+    assert_eq!(bloom.number_of_bits(), 80);
+    assert_eq!(bloom.count_ones(), 5);
+    assert_eq!(bloom.to_rle(), vec![(false, 10), (true, 3), (false, 50), (true, 2), (false, 15)]);
+
+    let mut filled = Bloom::new(100, 10);
+    for key in 0..10u64 {
+        filled.add(&key);
+    }
+    let rebuilt = Bloom::from_rle(&filled.to_rle(), filled.number_of_bits(), 7, filled.seeds()).unwrap();
+    assert_eq!(rebuilt.bitmap().to_bytes(), filled.bitmap().to_bytes());
+    assert_eq!(Bloom::from_rle(&rle, 81, 3, (1, 2)).err(), Some(BloomError::SizeMismatch));
+    assert_eq!(Bloom::from_rle(&[(true, u64::MAX), (true, 2)], 1, 3, (1, 2)).err(), Some(BloomError::SizeMismatch));
+    let huge = [(false, u64::MAX / 2), (true, 1)];
+    assert_eq!(Bloom::from_rle(&huge, u64::MAX / 2 + 1, 3, (1, 2)).err(), Some(BloomError::InvalidFormat));
+    let just_over = [(false, MAX_UNTRUSTED_BITS), (true, 1)];
+    assert_eq!(Bloom::from_rle(&just_over, MAX_UNTRUSTED_BITS + 1, 3, (1, 2)).err(), Some(BloomError::InvalidFormat));
+}
+
+#[test]
+fn bloom_test_count_confidence_interval() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let trials = 200;
+    let covered = (0..trials)
+        .filter(|_| {
+            let mut bloom = Bloom::new_with_rate(500, 0.01);
+            for _ in 0..500 {
+                bloom.add(&rng.gen::<u64>());
+            }
+            let (low, high) = bloom.count_confidence_interval(0.9);
+            assert!(low <= bloom.estimate_count() && bloom.estimate_count() <= high);
+            (low..=high).contains(&500)
+        })
+        .count();
+    assert!(covered as f64 / trials as f64 >= 0.85);
+    assert_eq!(Bloom::new(10, 10).count_confidence_interval(0.9), (0, 0));
+}
+
+#[test]
+fn bloom_test_add_with_k() {
+    let mut bloom = Bloom::new_with_rate(1_000, 0.05);
+    let k = bloom.number_of_hash_functions();
+    for key in 0..1_000u64 {
+        bloom.add(&key);
+    }
+    bloom.add_with_k(&"important", 3 * k);
+    assert!(bloom.check_with_k(&"important", 3 * k));
+    assert!(bloom.check(&"important"));
+    assert!((0..1_000u64).all(|key| bloom.check_with_k(&key, k)));
+
+    let mut rng = StdRng::seed_from_u64(9);
+    let absent: Vec<u64> = (0..20_000).map(|_| rng.gen_range(1_000, u64::MAX)).collect();
+    let fp_default = absent.iter().filter(|key| bloom.check_with_k(*key, k)).count();
+    let fp_important = absent.iter().filter(|key| bloom.check_with_k(*key, 3 * k)).count();
+    assert!(fp_default > 0);
+    assert!(fp_important * 10 < fp_default);
+}
+
+#[test]
+fn bloom_test_expected_false_negative_rate() {
+    let mut bloom = Bloom::new_with_rate(10_000, 0.001);
+    let recorded = (0..10_000u64).filter(|key| bloom.add_sampled(key, 0.3)).count();
+    assert_eq!(recorded as u64, bloom.insertions());
+    let missed = (0..10_000u64).filter(|key| !bloom.check(key)).count();
+    let empirical = missed as f64 / 10_000.0;
+    assert!((empirical - bloom.expected_false_negative_rate(0.3)).abs() < 0.03);
+    assert!(!bloom.add_sampled(&"never", 0.0));
+    assert!(bloom.add_sampled(&"always", 1.0));
+    assert_eq!(bloom.expected_false_negative_rate(1.0), 0.0);
+}
+
+#[test]
+fn bloom_test_config_hash() {
+    let mut a = Bloom::new_for_union(100, 0.01, (1, 2));
+    let b = Bloom::new_for_union(100, 0.01, (1, 2));
+    a.add(&1u64);
+    assert_eq!(a.config_hash(), b.config_hash());
+    assert_ne!(a.config_hash(), Bloom::new_for_union(100, 0.01, (2, 1)).config_hash());
+    assert_ne!(a.config_hash(), Bloom::new_for_union(200, 0.01, (1, 2)).config_hash());
+    assert_ne!(a.config_hash(), Bloom::from_existing(&a.bitmap().to_bytes(), a.number_of_bits(), 1, (1, 2)).config_hash());
+}
+
+#[test]
+fn bloom_test_from_upstream() {
+    let mut blob = vec![1u8];
+    blob.extend_from_slice(&16u64.to_le_bytes());
+    blob.extend_from_slice(&7u32.to_le_bytes());
+    blob.extend_from_slice(&[0x5a; 32]);
+    blob.extend_from_slice(&[0; 16]);
+    assert_eq!(Bloom::upstream_params(&blob), Ok((128, 7)));
+    assert_eq!(Bloom::from_upstream(&blob).err(), Some(BloomError::SchemeMismatch(UPSTREAM_HASH_SCHEME)));
+
+    assert_eq!(Bloom::upstream_params(&blob[..blob.len() - 1]), Err(BloomError::InvalidFormat));
+    assert_eq!(Bloom::upstream_params(&blob[..10]), Err(BloomError::InvalidFormat));
+    blob[0] = 2;
+    assert_eq!(Bloom::from_upstream(&blob).err(), Some(BloomError::UnsupportedVersion(2)));
+    assert_eq!(Bloom::upstream_params(&Bloom::new(16, 10).to_bytes()), Err(BloomError::UnsupportedVersion(b'X')));
+}
+
+#[test]
+fn bloom_test_optimal_serialized_bits() {
+    let half = Bloom::from_rle(&[(true, 512), (false, 512)], 1024, 3, (1, 2)).unwrap();
+    assert_eq!(half.optimal_serialized_bits(), 1024.0);
+
+    let mut sparse = Bloom::new(1024, 1000);
+    sparse.add(&1u64);
+    assert!(sparse.optimal_serialized_bits() * 100.0 < sparse.number_of_bits() as f64);
+    assert_eq!(Bloom::new(10, 10).optimal_serialized_bits(), 0.0);
+}
+
+#[test]
+fn bloom_test_add_batch_if_all_absent() {
+    let mut bloom = Bloom::new_with_rate(100, 0.001);
+    bloom.add(&7u64);
+    let (ones, root) = (bloom.count_ones(), bloom.content_root());
+    assert!(!bloom.add_batch_if_all_absent(&[5u64, 6, 7, 8]));
+    assert_eq!((bloom.count_ones(), bloom.content_root(), bloom.insertions()), (ones, root, 1));
+    assert!(!bloom.check(&5u64));
+
+    assert!(bloom.add_batch_if_all_absent(&[5u64, 6, 8]));
+    assert!([5u64, 6, 7, 8].iter().all(|key| bloom.check(key)));
+    assert_eq!(bloom.insertions(), 4);
+    assert!(bloom.add_batch_if_all_absent::<u64>(&[]));
+}
+
+#[test]
+fn bloom_test_hash_increment() {
+    let bloom = Bloom::new(1024, 100);
+    let mut rng = StdRng::seed_from_u64(4);
+    let increments: Vec<u64> = (0..10_000).map(|_| bloom.hash_increment(&rng.gen::<u64>())).collect();
+    assert!(increments.iter().all(|&increment| increment < bloom.number_of_bits()));
+    assert!(increments.iter().filter(|&&increment| increment == 0).count() <= 5);
+    let mean = increments.iter().sum::<u64>() as f64 / increments.len() as f64;
+    assert!((mean / bloom.number_of_bits() as f64 - 0.5).abs() < 0.02);
+    let mut quarters = [0usize; 4];
+    for increment in increments {
+        quarters[(increment * 4 / bloom.number_of_bits()) as usize] += 1;
+    }
+    assert!(quarters.iter().all(|&count| (2_300..2_700).contains(&count)));
+
+    let key = 42u64;
+    let offsets = bloom.probe_offsets(&key);
+    let step = (offsets[1] as u64 + bloom.number_of_bits() - offsets[0] as u64) % bloom.number_of_bits();
+    // 8192 bits divide 2^64, so the wraparound doesn't change the step
+    assert_eq!(step, bloom.hash_increment(&key));
+}
+
+#[test]
+fn bloom_test_hybrid_hottest_regions() {
+    let mut bloom = HybridBloom::new(1_000, 1_000, 64);
+    for key in 0..1_000u64 {
+        bloom.add(&key);
+    }
+    for _ in 0..500 {
+        bloom.add(&"hot");
+    }
+    for _ in 0..200 {
+        bloom.add(&"warm");
+    }
+    assert!(bloom.check(&"hot") && bloom.check(&999u64));
+    assert_eq!(bloom.bloom().insertions(), 1_700);
+
+    let hottest = bloom.hottest_regions(2);
+    assert_eq!(hottest.len(), 2);
+    assert_eq!(hottest[0].0, bloom.region_of(&"hot"));
+    assert!(hottest[0].1 >= 500);
+    if bloom.region_of(&"warm") != bloom.region_of(&"hot") {
+        assert_eq!(hottest[1].0, bloom.region_of(&"warm"));
+    }
+    assert_eq!(bloom.hottest_regions(100).iter().map(|region| region.1).sum::<u64>(), 1_700);
+}
+
+#[test]
+fn bloom_test_assert_roundtrip_stable() {
+    let mut rng = StdRng::seed_from_u64(8);
+    for &bitmap_size in &[1u64, 7, 8, 9, 63, 100, 1_001, 4_096, 1 << 20] {
+        let bloom = Bloom::random(bitmap_size, 3, (bitmap_size, 2), 0.3, rng.gen());
+        bloom.assert_roundtrip_stable();
+        assert_eq!(Bloom::from_bytes(&bloom.to_bytes()).unwrap().bitmap().len() as u64, bitmap_size);
+    }
+    let mut bloom = Bloom::new_with_rate(1_000, 0.01);
+    for key in 0..1_000u64 {
+        bloom.add(&key);
+    }
+    bloom.assert_roundtrip_stable();
+    assert!(Bloom::random(100, 3, (1, 2), 0.3, 1) != Bloom::random(100, 3, (1, 2), 0.3, 2));
+}
+
+#[test]
+fn bloom_test_effective_capacity() {
+    // 1000 bits for 600 items call for k = 1.16, rounded up to 2: the false positive rate
+    // an optimal k gives at 600 items is reached earlier.
+    let bloom = Bloom::new(125, 600);
+    assert_eq!(bloom.number_of_hash_functions(), 2);
+    let optimal_fp = (-(1_000.0 / 600.0) * f64::consts::LN_2.powi(2)).exp();
+    let capacity = bloom.effective_capacity(optimal_fp);
+    assert!((500..600).contains(&capacity));
+    assert!(Bloom::false_positive_rate(1_000, 2, capacity as f64) <= optimal_fp);
+    assert!(Bloom::false_positive_rate(1_000, 2, capacity as f64 + 1.0) > optimal_fp);
+
+    let designed = Bloom::new_with_rate(1_000, 0.01);
+    assert!((950..1_050).contains(&designed.effective_capacity(0.01)));
+}
+
+#[test]
+fn bloom_test_suggest_next_config() {
+    let mut bloom = Bloom::new_with_rate(100, 0.01);
+    for key in 0..1_000u64 {
+        bloom.add(&key);
+    }
+    let (bytes, k) = bloom.suggest_next_config(bloom.insertions(), 0.01);
+    assert!(bytes as u64 * 8 > 9 * bloom.number_of_bits());
+    assert_eq!(k, bloom.number_of_hash_functions());
+    let next = Bloom::new(bytes, 1_000);
+    assert_eq!(next.number_of_hash_functions(), k);
+    assert!(next.effective_capacity(0.01) >= 950);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn bloom_test_try_from_json() {
+    let mut bloom = Bloom::new_for_union(10, 0.01, (3, 4));
+    bloom.add(&1u64);
+    let config = serde_json::json!({
+        "bitmap_size": bloom.number_of_bits(),
+        "k": bloom.number_of_hash_functions(),
+        "seeds": [3, 4],
+        "bitmap": base64::engine::general_purpose::STANDARD.encode(bloom.bitmap().to_bytes()),
+    });
+    let parsed = Bloom::try_from(config.clone()).unwrap();
+    assert!(parsed == bloom);
+    assert!(parsed.check(&1u64));
+
+    let mut empty = config.clone();
+    empty.as_object_mut().unwrap().remove("bitmap");
+    let empty = Bloom::try_from(empty).unwrap();
+    assert!(empty.is_compatible(&bloom) && empty.count_ones() == 0);
+
+    let mut missing = config.clone();
+    missing.as_object_mut().unwrap().remove("k");
+    assert_eq!(Bloom::try_from(missing).err(), Some(BloomError::InvalidField("k")));
+    let mut bad_seeds = config.clone();
+    bad_seeds["seeds"] = serde_json::json!([3]);
+    assert_eq!(Bloom::try_from(bad_seeds).err(), Some(BloomError::InvalidField("seeds")));
+    for &bitmap_size in &[u64::MAX, MAX_UNTRUSTED_BITS + 1] {
+        let huge = serde_json::json!({ "bitmap_size": bitmap_size, "k": 3, "seeds": [3, 4] });
+        assert_eq!(Bloom::try_from(huge).err(), Some(BloomError::InvalidField("bitmap_size")));
+    }
+    let mut bad_bitmap = config;
+    bad_bitmap["bitmap"] = serde_json::json!("AAAA");
+    assert_eq!(Bloom::try_from(bad_bitmap).err(), Some(BloomError::InvalidField("bitmap")));
+}
+
+#[test]
+fn bloom_test_merge_benefit() {
+    let seeds = (31, 32);
+    let build = |keys: Range<u64>| {
+        let mut bloom = Bloom::new_for_union(10_000, 0.01, seeds);
+        for key in keys {
+            bloom.add(&key);
+        }
+        bloom
+    };
+    let shard = build(0..500);
+    assert_eq!(shard.merge_benefit(&build(100..200)), Ok(1.0));
+    assert!(shard.merge_benefit(&build(1_000..1_500)).unwrap() < 0.1);
+    assert_eq!(shard.merge_benefit(&build(0..0)), Ok(1.0));
+    assert_eq!(shard.merge_benefit(&Bloom::new_for_union(10_000, 0.01, (1, 2))), Err(BloomError::Incompatible));
+}
+
+#[test]
+fn bloom_test_new_reproducible() {
+    let mut session = StdRng::seed_from_u64(12);
+    let mut replay = StdRng::seed_from_u64(12);
+    let first = Bloom::new_reproducible(100, 10, &mut session);
+    let second = Bloom::new_reproducible(100, 10, &mut session);
+    assert!(first == Bloom::new_reproducible(100, 10, &mut replay));
+    assert!(second == Bloom::new_reproducible(100, 10, &mut replay));
+    assert_ne!(first.seeds(), second.seeds());
+    assert_eq!(first.probe_offsets(&1u64), Bloom::new_reproducible(100, 10, &mut StdRng::seed_from_u64(12)).probe_offsets(&1u64));
+}
+
+#[test]
+fn bloom_test_estimated_query_cycles() {
+    let small = Bloom::new_capped_k(1 << 10, 100, 7);
+    let large = Bloom::new_capped_k(8 << 20, 100, 7);
+    assert_eq!(small.number_of_hash_functions(), large.number_of_hash_functions());
+    assert_eq!(small.estimated_query_cycles(64, 200), 20.0 + 7.0 * 2.0);
+    let cycles = large.estimated_query_cycles(64, 200);
+    assert!(cycles > small.estimated_query_cycles(64, 200));
+    assert!((1_200.0..1_300.0).contains(&cycles));
+    assert!(large.estimated_query_cycles(64, 400) > cycles);
+}
+
+#[test]
+fn bloom_test_with_exact_ones() {
+    for &ones in &[0u64, 1, 500, 999, 1_000] {
+        let bloom = Bloom::with_exact_ones(1_000, 3, (1, 2), ones, 5).unwrap();
+        assert_eq!(bloom.count_ones(), ones);
+        assert_eq!(bloom.bitmap().iter().filter(|&bit| bit).count() as u64, ones);
+        assert!(bloom == Bloom::with_exact_ones(1_000, 3, (1, 2), ones, 5).unwrap());
+    }
+    assert_eq!(Bloom::with_exact_ones(1_000, 3, (1, 2), 1_001, 5).err(), Some(BloomError::TooManyOnes));
+    assert!(Bloom::approximate_from_stats(1_000, 3, (1, 2), 400, 5) == Bloom::with_exact_ones(1_000, 3, (1, 2), 400, 5).unwrap());
+}
+
+#[test]
+fn bloom_test_membership() {
+    fn count_present<M: Membership<u64>>(m: &M, keys: &[u64]) -> usize {
+        keys.iter().filter(|key| m.check(key)).count()
+    }
+    let keys: Vec<u64> = (0..100).collect();
+    let mut bloom = Bloom::new_with_rate(1_000, 0.001);
+    let mut hybrid = HybridBloom::new(1_000, 1_000, 8);
+    for key in &keys[..50] {
+        bloom.add(key);
+        hybrid.add(key);
+    }
+    let mut arena = vec![0u8; 1_000];
+    let mut buffer = Bloom::new_in_buffer(&mut arena, 0, 8_000, 4, (1, 2)).unwrap();
+    for key in &keys[..50] {
+        buffer.add(key);
+    }
+    assert!((50..53).contains(&count_present(&bloom, &keys)));
+    assert!((50..53).contains(&count_present(&hybrid, &keys)));
+    assert!((50..53).contains(&count_present(&buffer, &keys)));
+    let expected = count_present(&bloom, &keys);
+    assert_eq!(count_present(&bloom.seal(), &keys), expected);
 }